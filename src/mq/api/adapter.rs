@@ -0,0 +1,122 @@
+use crate::mq::api::message::Message;
+use crate::mq::api::queue::Queue;
+use crate::mq::error::{MqError, MqResult};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// without an idle timeout the queue is waited on in steps of this length
+const WAIT_STEP: Duration = Duration::from_secs(1);
+
+// Blocking iterator over the messages of a queue. It ends once the queue stayed empty for the
+// idle timeout, if one is set, or after reporting that the channel was closed.
+pub struct Messages {
+    queue: Queue,
+    idle_timeout: Option<Duration>,
+    done: bool,
+    // set once nobody listens any more, checked after every empty wait step
+    stop: Arc<AtomicBool>,
+}
+
+impl Messages {
+    pub fn new(queue: Queue) -> Messages {
+        Messages {
+            queue,
+            idle_timeout: None,
+            done: false,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Messages {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    // Hands the messages to a background thread. The thread stops when the iterator ends or the
+    // receiver is dropped, at the latest one wait step later on an empty queue. It lets go of the
+    // queue then, a message fetched after the receiver went away is lost.
+    pub fn into_receiver(self) -> MessageReceiver {
+        let (tx, rx) = channel();
+        let stop = self.stop.clone();
+        thread::spawn(move || {
+            for message in self {
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        MessageReceiver { rx, stop }
+    }
+}
+
+impl Iterator for Messages {
+    type Item = MqResult<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let deadline = self.idle_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let step = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => WAIT_STEP,
+            };
+            match self.queue.fetch_timeout(step) {
+                Ok(Some(message)) => return Some(Ok(message)),
+                Ok(None) if deadline.is_some() || self.stop.load(Ordering::SeqCst) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(None) => continue,
+                Err(err) => {
+                    self.done = matches!(err, MqError::ChannelClosed(_));
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+// The receiving end of Messages::into_receiver, used like the Receiver it wraps. Dropping it
+// stops the background thread.
+pub struct MessageReceiver {
+    rx: Receiver<MqResult<Message>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Deref for MessageReceiver {
+    type Target = Receiver<MqResult<Message>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.rx
+    }
+}
+
+impl Drop for MessageReceiver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+impl IntoIterator for Queue {
+    type Item = MqResult<Message>;
+    type IntoIter = Messages;
+
+    fn into_iter(self) -> Messages {
+        Messages::new(self)
+    }
+}
+
+impl IntoIterator for &Queue {
+    type Item = MqResult<Message>;
+    type IntoIter = Messages;
+
+    fn into_iter(self) -> Messages {
+        self.iter()
+    }
+}
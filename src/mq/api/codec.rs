@@ -0,0 +1,177 @@
+use crate::mq::api::message::{self, Message};
+use crate::mq::api::queue::Queue;
+use crate::mq::error::{MqError, MqResult};
+use crate::mq::io::ack::AckMode;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+// Turns values into message bodies and back. A body that cannot be decoded is a
+// MqError::Codec, never a panic.
+pub trait MessageCodec<T> {
+    fn encode(&self, value: &T) -> MqResult<Vec<u8>>;
+    fn decode(&self, body: &[u8]) -> MqResult<T>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Utf8Codec;
+
+impl MessageCodec<String> for Utf8Codec {
+    fn encode(&self, value: &String) -> MqResult<Vec<u8>> {
+        Ok(value.as_bytes().to_vec())
+    }
+
+    fn decode(&self, body: &[u8]) -> MqResult<String> {
+        String::from_utf8(body.to_vec()).map_err(|err| MqError::Codec(err.to_string()))
+    }
+}
+
+// the body as it is
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RawCodec;
+
+impl MessageCodec<Vec<u8>> for RawCodec {
+    fn encode(&self, value: &Vec<u8>) -> MqResult<Vec<u8>> {
+        Ok(value.clone())
+    }
+
+    fn decode(&self, body: &[u8]) -> MqResult<Vec<u8>> {
+        Ok(body.to_vec())
+    }
+}
+
+// A list of fields, each prefixed with its length as a little-endian u32, the same format
+// batch frames use for their messages.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LengthPrefixedCodec;
+
+impl MessageCodec<Vec<Vec<u8>>> for LengthPrefixedCodec {
+    fn encode(&self, value: &Vec<Vec<u8>>) -> MqResult<Vec<u8>> {
+        let mut body = Vec::with_capacity(value.iter().map(|field| 4 + field.len()).sum());
+        for field in value {
+            if u32::try_from(field.len()).is_err() {
+                return Err(MqError::Codec(format!("field of {} bytes is too long for a u32 prefix", field.len())));
+            }
+            message::pack(&mut body, field);
+        }
+        Ok(body)
+    }
+
+    fn decode(&self, mut body: &[u8]) -> MqResult<Vec<Vec<u8>>> {
+        let mut fields = vec![];
+        while !body.is_empty() {
+            let len = body.get(0..4)
+                .map(|len| u32::from_le_bytes(<[u8; 4]>::try_from(len).unwrap()) as usize)
+                .filter(|len| 4 + len <= body.len())
+                .ok_or_else(|| MqError::Codec(format!("body ends in the middle of field {}", fields.len())))?;
+            fields.push(body[4..4 + len].to_vec());
+            body = &body[4 + len..];
+        }
+        Ok(fields)
+    }
+}
+
+// A queue that pushes and fetches values of one type. A message that does not decode is
+// rejected if the channel uses manual acks, so that it does not come back.
+pub struct TypedQueue<T, C> {
+    queue: Queue,
+    codec: C,
+    marker: PhantomData<fn(T) -> T>,
+}
+
+impl<T, C: MessageCodec<T>> TypedQueue<T, C> {
+    pub fn new(queue: Queue, codec: C) -> TypedQueue<T, C> {
+        TypedQueue {
+            queue,
+            codec,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn into_queue(self) -> Queue {
+        self.queue
+    }
+
+    pub fn push(&self, value: &T) -> MqResult<()> {
+        self.queue.push(self.codec.encode(value)?)
+    }
+
+    pub fn push_confirmed(&self, value: &T, timeout: Duration) -> MqResult<()> {
+        self.queue.push_confirmed(self.codec.encode(value)?, timeout)
+    }
+
+    // nothing is pushed unless every value encodes
+    pub fn push_batch(&self, values: &[T]) -> MqResult<()> {
+        let batch = values.iter().map(|value| self.codec.encode(value)).collect::<MqResult<Vec<_>>>()?;
+        self.queue.push_batch(batch)
+    }
+
+    // Only with auto acks, with manual acks the value would come without the message
+    // to settle it, use the fetch_message variants instead.
+    pub fn fetch(&self) -> MqResult<Option<T>> {
+        self.values_only()?;
+        Ok(self.fetch_message()?.map(|(value, _)| value))
+    }
+
+    pub fn fetch_timeout(&self, timeout: Duration) -> MqResult<Option<T>> {
+        self.values_only()?;
+        Ok(self.fetch_message_timeout(timeout)?.map(|(value, _)| value))
+    }
+
+    // a message that does not decode leaves an error in its place, the others still arrive
+    pub fn fetch_batch(&self, max: usize, timeout: Duration) -> MqResult<Vec<MqResult<T>>> {
+        self.values_only()?;
+        let values = self.fetch_message_batch(max, timeout)?;
+        Ok(values.into_iter().map(|value| value.map(|(value, _)| value)).collect())
+    }
+
+    // the message comes along for its properties, and to be acked with manual acks
+    pub fn fetch_message(&self) -> MqResult<Option<(T, Message)>> {
+        self.queue.fetch()?.map(|message| self.decode(message)).transpose()
+    }
+
+    pub fn fetch_message_timeout(&self, timeout: Duration) -> MqResult<Option<(T, Message)>> {
+        self.queue.fetch_timeout(timeout)?.map(|message| self.decode(message)).transpose()
+    }
+
+    pub fn fetch_message_batch(&self, max: usize, timeout: Duration) -> MqResult<Vec<MqResult<(T, Message)>>> {
+        let messages = self.queue.fetch_batch(max, timeout)?;
+        Ok(messages.into_iter().map(|message| self.decode(message)).collect())
+    }
+
+    fn values_only(&self) -> MqResult<()> {
+        if self.queue.ack_mode() == AckMode::Manual {
+            return Err(MqError::Validation(String::from("values alone cannot be acked, fetch messages with manual acks")));
+        }
+        Ok(())
+    }
+
+    fn decode(&self, message: Message) -> MqResult<(T, Message)> {
+        match message.decode(&self.codec) {
+            Ok(value) => Ok((value, message)),
+            Err(err) => {
+                if message.needs_ack() {
+                    message.reject()?;
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<T, C: Clone> Clone for TypedQueue<T, C> {
+    fn clone(&self) -> Self {
+        TypedQueue {
+            queue: self.queue.clone(),
+            codec: self.codec.clone(),
+            marker: PhantomData,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use crate::mq::api::codec::MessageCodec;
+use crate::mq::error::{ErrorCode, MqError, MqResult};
+use crate::mq::io::channel::{ChannelClosed, ChannelHandle, ChannelState};
+use crate::mq::io::factory::{AckKind, Command, Routing, RoutingType};
+use crate::mq::io::pool::Payload;
+use crate::mq::protocol::proto::DataHead;
+use crate::mq::routing::chain::RoutingChain;
+use std::sync::{Arc, Weak};
+
+// Head fields that say something about the frame rather than where it was routed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageProperties {
+    pub version: [u8; 4],
+    pub command: Option<Command>,
+    // number of messages the broker put into the frame
+    pub count: u32,
+    pub ack: u16,
+}
+
+// A frame received for a channel, decoded from its head. The body is cut to the length
+// the sender wrote, so the padding of the wire format never reaches the caller.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub virtual_host: String,
+    pub channel: String,
+    pub routing_type: RoutingType,
+    pub routing: RoutingChain,
+    pub errcode: u16,
+    pub properties: MessageProperties,
+    pub delivery_tag: u64,
+    body: Payload,
+    // set for messages fetched with AckMode::Manual, which the broker holds until they are settled
+    acker: Option<Weak<ChannelHandle>>,
+}
+
+impl Message {
+    pub fn from_frame(head: DataHead, payload: Payload) -> Message {
+        let body = match head.body_len() {
+            Some(len) if len <= payload.len() => payload.slice(0..len),
+            // senders that do not fill in the length get the padded payload, as before
+            _ => payload,
+        };
+        Message {
+            virtual_host: trim(&head.virtual_host),
+            channel: trim(&head.channel),
+            routing_type: RoutingType::parse(head.routing_mod[2]),
+            routing: RoutingChain::new(
+                [route(&head.route0), route(&head.route1), route(&head.route2)],
+                trim(&head.route3)
+            ),
+            errcode: head.errcode,
+            properties: MessageProperties {
+                version: head.version,
+                command: Command::parse(&head.command),
+                count: head.count,
+                ack: head.ack,
+            },
+            delivery_tag: head.delivery_tag(),
+            body,
+            acker: None,
+        }
+    }
+
+    // registers the delivery with the channel it was fetched on, so that it can be settled
+    pub(crate) fn tracked(mut self, channel: &Arc<ChannelHandle>) -> Message {
+        if self.delivery_tag != 0 {
+            channel.track(self.delivery_tag);
+            self.acker = Some(Arc::downgrade(channel));
+        }
+        self
+    }
+
+    // drops the first `len` bytes of the body, for headers carried in front of it
+    pub(crate) fn skip(mut self, len: usize) -> Message {
+        self.body = self.body.slice(len..self.body.len());
+        self
+    }
+
+    // true until a message fetched with AckMode::Manual was acked, nacked or rejected
+    pub fn needs_ack(&self) -> bool {
+        self.acker.as_ref().and_then(Weak::upgrade).is_some_and(|channel| channel.is_unacked(self.delivery_tag))
+    }
+
+    pub fn ack(&self) -> MqResult<()> {
+        self.settle(AckKind::Ack)
+    }
+
+    // hands the message back to the broker, to be delivered again or dropped
+    pub fn nack(&self, requeue: bool) -> MqResult<()> {
+        self.settle(if requeue { AckKind::Requeue } else { AckKind::Drop })
+    }
+
+    pub fn reject(&self) -> MqResult<()> {
+        self.settle(AckKind::Reject)
+    }
+
+    fn settle(&self, kind: AckKind) -> MqResult<()> {
+        let Some(acker) = &self.acker else {
+            // the broker settled it when it was fetched
+            return match kind {
+                AckKind::Ack => Ok(()),
+                _ => Err(MqError::Validation(String::from("message was acknowledged on delivery and cannot be returned"))),
+            };
+        };
+        match acker.upgrade() {
+            Some(channel) => channel.settle(self.delivery_tag, kind),
+            // the last handle on the channel is gone, and with it the channel
+            None => Err(MqError::ChannelClosed(ChannelClosed {
+                channel: self.channel.clone(),
+                state: ChannelState::Closed,
+            })),
+        }
+    }
+
+    // Splits a frame carrying `DataHead.count` messages, each prefixed with its length as a
+    // little-endian u32. The broker tags such a frame with the tag of its first message.
+    pub fn unpack(head: DataHead, payload: Payload) -> MqResult<Vec<Message>> {
+        let count = head.count as usize;
+        let packed = Message::from_frame(head, payload);
+        if count == 0 {
+            return Ok(vec![packed]);
+        }
+        let mut messages = Vec::with_capacity(count);
+        let mut offset = 0;
+        for i in 0..count {
+            let len = packed.body.get(offset..offset + 4)
+                .map(|len| u32::from_le_bytes(<[u8; 4]>::try_from(len).unwrap()) as usize)
+                .filter(|len| offset + 4 + len <= packed.len())
+                .ok_or_else(|| MqError::Protocol(format!("batch frame ends before message {i} of {count}")))?;
+            messages.push(Message {
+                delivery_tag: if packed.delivery_tag == 0 { 0 } else { packed.delivery_tag + i as u64 },
+                body: packed.body.slice(offset + 4..offset + 4 + len),
+                ..packed.clone()
+            });
+            offset += 4 + len;
+        }
+        Ok(messages)
+    }
+
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        ErrorCode::from_errcode(self.errcode)
+    }
+
+    pub fn queue_name(&self) -> &String {
+        &self.routing.queue_name
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.body
+    }
+
+    pub fn len(&self) -> usize {
+        self.body.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty()
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body.to_vec()
+    }
+
+    pub fn body_string(&self) -> MqResult<String> {
+        Ok(String::from_utf8(self.body.to_vec())?)
+    }
+
+    pub fn decode<T, C: MessageCodec<T>>(&self, codec: &C) -> MqResult<T> {
+        codec.decode(&self.body)
+    }
+}
+
+// appends one message to a payload in the format `unpack` reads
+pub fn pack(packed: &mut Vec<u8>, body: &[u8]) {
+    packed.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    packed.extend_from_slice(body);
+}
+
+fn trim(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string()
+}
+
+// the inverse of MessageFactory::route, unused keys come back as Stop
+fn route(bytes: &[u8; 32]) -> Routing {
+    match trim(bytes).as_str() {
+        "" | "!" => Routing::Stop,
+        "*" => Routing::Any,
+        key => Routing::Route(key.to_string()),
+    }
+}
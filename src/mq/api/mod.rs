@@ -1,2 +1,8 @@
 pub mod common;
-pub mod queue;
\ No newline at end of file
+pub mod queue;
+pub mod message;
+pub mod subscription;
+pub mod adapter;
+pub mod prefetch;
+pub mod rpc;
+pub mod codec;
\ No newline at end of file
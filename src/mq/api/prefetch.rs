@@ -0,0 +1,141 @@
+use crate::mq::api::message::Message;
+use crate::mq::api::queue::Queue;
+use crate::mq::error::{MqError, MqResult};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how long one fetch of the background fetcher waits on an empty queue
+const FETCH_STEP: Duration = Duration::from_millis(100);
+// how often a full window is checked for messages that were settled in the meantime
+const WINDOW_CHECK: Duration = Duration::from_millis(10);
+const ERROR_PAUSE: Duration = Duration::from_millis(100);
+// longest a fetch without a timeout waits for the fetcher, e.g. while the window is full of unacked messages
+const ROUND_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Buffer {
+    messages: VecDeque<MqResult<Message>>,
+    // fetches the fetcher finished, lets a fetch on an empty buffer wait for the next answer
+    rounds: u64,
+}
+
+struct Shared {
+    buffer: Mutex<Buffer>,
+    changed: Condvar,
+    stopped: AtomicBool,
+}
+
+// Keeps the channel's prefetch window filled ahead of a consumer, see Channel::set_prefetch.
+// Stops once dropped. Messages still buffered then are handed back to the broker with manual
+// acks; with automatic acks the broker already forgot them and they are lost.
+pub struct Prefetcher {
+    shared: Arc<Shared>,
+}
+
+impl Prefetcher {
+    // `queue` fetches straight from the broker, it must not have a prefetcher of its own
+    pub fn spawn(queue: Queue) -> Prefetcher {
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(Buffer::default()),
+            changed: Condvar::new(),
+            stopped: AtomicBool::new(false),
+        });
+        let worker = shared.clone();
+        thread::spawn(move || {
+            while !worker.stopped.load(Ordering::SeqCst) {
+                let room = queue.prefetch_room(worker.buffer.lock().unwrap().messages.len());
+                if room == 0 {
+                    let buffer = worker.buffer.lock().unwrap();
+                    let _ = worker.changed.wait_timeout(buffer, WINDOW_CHECK).unwrap();
+                    continue;
+                }
+                let fetched = queue.fetch_direct(room, FETCH_STEP);
+                let closed = matches!(fetched, Err(MqError::ChannelClosed(_)));
+                let failed = fetched.is_err();
+                if worker.stopped.load(Ordering::SeqCst) {
+                    give_back(fetched.into_iter().flatten().map(Ok));
+                    break;
+                }
+                {
+                    let mut buffer = worker.buffer.lock().unwrap();
+                    match fetched {
+                        Ok(messages) => buffer.messages.extend(messages.into_iter().map(Ok)),
+                        Err(err) => buffer.messages.push_back(Err(err)),
+                    }
+                    buffer.rounds += 1;
+                }
+                worker.changed.notify_all();
+                if closed {
+                    break;
+                }
+                if failed {
+                    thread::sleep(ERROR_PAUSE);
+                }
+            }
+        });
+        Prefetcher { shared }
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.shared.buffer.lock().unwrap().messages.len()
+    }
+
+    // Waits up to `timeout` for a message. With no timeout it waits for the fetcher to come back
+    // from the broker, so an empty queue reads as empty rather than as a buffer not filled yet.
+    pub fn take(&self, timeout: Option<Duration>) -> MqResult<Option<Message>> {
+        Ok(self.take_up_to(1, timeout)?.pop())
+    }
+
+    pub fn take_up_to(&self, max: usize, timeout: Option<Duration>) -> MqResult<Vec<Message>> {
+        let deadline = Instant::now() + timeout.unwrap_or(ROUND_WAIT);
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        // a round may already be under way, the one after it certainly started after us
+        let round = buffer.rounds + 2;
+        loop {
+            if !buffer.messages.is_empty() {
+                break;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || (timeout.is_none() && buffer.rounds >= round) {
+                return Ok(vec![]);
+            }
+            buffer = self.shared.changed.wait_timeout(buffer, remaining).unwrap().0;
+        }
+        let mut messages = vec![];
+        while messages.len() < max {
+            match buffer.messages.pop_front() {
+                Some(Ok(message)) => messages.push(message),
+                // an error is only reported once the messages before it are taken
+                Some(Err(err)) if messages.is_empty() => return Err(err),
+                Some(Err(err)) => {
+                    buffer.messages.push_front(Err(err));
+                    break;
+                }
+                None => break,
+            }
+        }
+        drop(buffer);
+        self.shared.changed.notify_all();
+        Ok(messages)
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        self.shared.changed.notify_all();
+        give_back(std::mem::take(&mut self.shared.buffer.lock().unwrap().messages));
+    }
+}
+
+// last one first, a broker that puts requeued messages in front then keeps them in order
+fn give_back<I: IntoIterator<Item = MqResult<Message>>>(messages: I) {
+    for message in messages.into_iter().flatten().collect::<Vec<_>>().into_iter().rev() {
+        if message.needs_ack() {
+            let _ = message.nack(true);
+        }
+    }
+}
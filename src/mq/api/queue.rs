@@ -1,89 +1,395 @@
+use crate::mq::api::adapter::{MessageReceiver, Messages};
+use crate::mq::api::codec::{MessageCodec, TypedQueue, Utf8Codec};
 use crate::mq::api::common::{FetchResult, FetchResultString};
-use crate::mq::io::factory::{DataType, MessageFactory, MessageType, RoutingModFactory, RoutingType};
+use crate::mq::api::message::{self, Message};
+use crate::mq::api::prefetch::Prefetcher;
+use crate::mq::api::subscription::{HandlerResult, Subscription, SubscriptionConfig};
+use crate::mq::error::{ErrorCode, MqError, MqResult};
+use crate::mq::io::ack::AckMode;
+use crate::mq::io::channel::ChannelHandle;
+use crate::mq::io::confirm::{Confirmation, PendingConfirms};
+use crate::mq::io::factory::{AckKind, DataType, MessageType, RoutingModFactory, RoutingType};
+use crate::mq::io::frame::Frame;
+use crate::mq::io::pool::Payload;
 use crate::mq::io::session::Session;
 use crate::mq::protocol::proto::DataHead;
 use crate::mq::routing::chain::RoutingChain;
-use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
+// pauses between two fetches while fetch_timeout polls an empty queue
+const POLL_MIN_PAUSE: Duration = Duration::from_millis(1);
+const POLL_MAX_PAUSE: Duration = Duration::from_millis(100);
+
+#[derive(Clone)]
 pub struct Queue {
     routing_chain: RoutingChain,
-    channel_name: String,
-    host_name: String,
+    // keeps the channel open for as long as the queue is around
+    channel: Arc<ChannelHandle>,
     session: Arc<RwLock<Session>>,
+    // started by the first fetch once the channel has a prefetch window, shared by the clones
+    prefetcher: Arc<Mutex<Option<Arc<Prefetcher>>>>,
 }
 
 impl Queue {
-    pub fn new(routing_chain: RoutingChain, channel_name: String, host_name: String, session: Arc<RwLock<Session>>) -> Queue {
+    pub fn new(routing_chain: RoutingChain, channel: Arc<ChannelHandle>, session: Arc<RwLock<Session>>) -> Queue {
         Queue {
             routing_chain,
-            channel_name,
-            host_name,
+            channel,
             session,
+            prefetcher: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // another queue on the same channel, with a prefetch buffer of its own
+    pub fn for_routing(&self, routing_chain: RoutingChain) -> Queue {
+        Queue::new(routing_chain, self.channel.clone(), self.session.clone())
+    }
+
+    // Not confirmed even on a channel in confirm mode, nobody would collect the confirm.
+    // Use push_pending or push_confirmed for that.
+    pub fn push(&self, data: Vec<u8>) -> MqResult<()> {
+        self.channel.status.check()?;
+        let session = self.session.read().unwrap();
+        self.send_push(&session, data, 0, false).map(|_| ())
+    }
+
+    // Pushes without waiting for the broker and returns the sequence number its confirm will
+    // carry. Only for channels in confirm mode.
+    pub fn push_pending(&self, data: Vec<u8>) -> MqResult<u64> {
+        if !self.channel.confirms_enabled() {
+            return Err(MqError::Validation(format!("channel {} is not in confirm mode", self.channel.status.name)));
+        }
+        self.channel.status.check()?;
+        let session = self.session.read().unwrap();
+        Ok(self.send_push(&session, data, 0, true)?.unwrap())
+    }
+
+    // blocks until the broker confirmed that it stored the message
+    pub fn push_confirmed(&self, data: Vec<u8>, timeout: Duration) -> MqResult<()> {
+        let seq = self.push_pending(data)?;
+        match self.confirms().wait(seq, timeout)? {
+            Confirmation::Acked => Ok(()),
+            confirmation => Err(MqError::Unconfirmed { seq, confirmation }),
+        }
+    }
+
+    pub fn confirms(&self) -> PendingConfirms {
+        self.channel.pending_confirms(self.session.clone())
+    }
+
+    // Brokers that take several messages per frame get as few frames as max_frame_size allows,
+    // others get one frame per message, written back to back without waiting for the broker.
+    // Not confirmed, like push.
+    pub fn push_batch(&self, batch: Vec<Vec<u8>>) -> MqResult<()> {
+        self.channel.status.check()?;
+        let session = self.session.read().unwrap();
+        if !session.config().multi_message {
+            for data in batch {
+                self.send_push(&session, data, 0, false)?;
+            }
+            return Ok(());
+        }
+        let limit = session.config().max_frame_size;
+        let mut packed = vec![];
+        let mut count = 0;
+        for data in batch {
+            if count > 0 && packed.len() + 4 + data.len() > limit {
+                self.send_push(&session, std::mem::take(&mut packed), count, false)?;
+                count = 0;
+            }
+            message::pack(&mut packed, &data);
+            count += 1;
+        }
+        if count > 0 {
+            self.send_push(&session, packed, count, false)?;
+        }
+        Ok(())
+    }
+
+    // None when the queue is empty
+    pub fn fetch(&self) -> MqResult<Option<Message>> {
+        if let Some(prefetcher) = self.prefetcher()? {
+            return prefetcher.take(None);
+        }
+        let (head, data) = self.exchange(None, 0)?;
+        self.message(head, data)
+    }
+
+    // Blocks until a message arrives or `timeout` is over. Brokers with long polling hold the
+    // fetch themselves, otherwise the queue is polled with growing pauses in between.
+    pub fn fetch_timeout(&self, timeout: Duration) -> MqResult<Option<Message>> {
+        if let Some(prefetcher) = self.prefetcher()? {
+            return prefetcher.take(Some(timeout));
+        }
+        self.fetch_one(timeout)
+    }
+
+    // Waits like fetch_timeout for the first message and returns up to `max` messages, or none
+    // if the queue stayed empty. Without multi-message frames the rest is fetched pipelined.
+    pub fn fetch_batch(&self, max: usize, timeout: Duration) -> MqResult<Vec<Message>> {
+        if max == 0 {
+            return Ok(vec![]);
+        }
+        if let Some(prefetcher) = self.prefetcher()? {
+            return prefetcher.take_up_to(max, Some(timeout));
+        }
+        self.fetch_direct(max, timeout)
+    }
+
+    pub fn ack_mode(&self) -> AckMode {
+        self.channel.ack_mode()
+    }
+
+    // messages fetched ahead of the consumer and not taken yet, see Channel::set_prefetch
+    pub fn buffered(&self) -> usize {
+        self.prefetcher.lock().unwrap().as_ref().map_or(0, |prefetcher| prefetcher.buffered())
+    }
+
+    // fetch_batch without the prefetch buffer, for the fetcher that fills it
+    pub(crate) fn fetch_direct(&self, max: usize, timeout: Duration) -> MqResult<Vec<Message>> {
+        if self.session.read().unwrap().config().multi_message {
+            let messages = self.poll(timeout, |wait| {
+                let (head, data) = self.exchange(wait, max as u32)?;
+                let messages = self.messages(head, data, true)?;
+                Ok(Some(messages).filter(|messages| !messages.is_empty()))
+            })?;
+            return Ok(messages.unwrap_or_default());
+        }
+
+        let Some(first) = self.fetch_one(timeout)? else {
+            return Ok(vec![]);
+        };
+        let mut messages = vec![first];
+        // From here on a failure only ends the batch early, the messages already taken off the
+        // broker are handed out.
+        if self.channel.status.check().is_err() {
+            return Ok(messages);
         }
+        // all fetches go out before the first reply is read, one round trip for the whole batch
+        let (sent, deadline) = {
+            let session = self.session.read().unwrap();
+            let sent = (1..max).take_while(|_| session.send(self.fetch_frame(&session, None, 0)).is_ok()).count();
+            (sent, Instant::now() + session.config().read_timeout)
+        };
+        for _ in 0..sent {
+            match self.read_reply(deadline).and_then(|(head, data)| self.message(head, data)) {
+                Ok(message) => messages.extend(message),
+                Err(_) => break,
+            }
+        }
+        Ok(messages)
     }
 
-    pub fn push(&self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    // how many more messages the fetcher may buffer, next to the `buffered` it holds already
+    pub(crate) fn prefetch_room(&self, buffered: usize) -> usize {
+        let held = match self.channel.ack_mode() {
+            AckMode::Auto => buffered,
+            // buffered messages were tracked when they were fetched
+            AckMode::Manual => self.channel.unacked().max(buffered),
+        };
+        self.channel.prefetch().saturating_sub(held)
+    }
+
+    // Keeps on using a prefetcher for as long as it has messages buffered, even once the window
+    // was turned off. A closed channel is reported once the buffer is empty.
+    fn prefetcher(&self) -> MqResult<Option<Arc<Prefetcher>>> {
+        let mut prefetcher = self.prefetcher.lock().unwrap();
+        if prefetcher.is_none() && self.channel.prefetch() > 0 {
+            self.channel.status.check()?;
+            let direct = Queue::new(self.routing_chain.clone(), self.channel.clone(), self.session.clone());
+            *prefetcher = Some(Arc::new(Prefetcher::spawn(direct)));
+        }
+        let Some(prefetcher) = prefetcher.clone() else {
+            return Ok(None);
+        };
+        if prefetcher.buffered() == 0 {
+            self.channel.status.check()?;
+            if self.channel.prefetch() == 0 {
+                return Ok(None);
+            }
+        }
+        Ok(Some(prefetcher))
+    }
+
+    fn fetch_one(&self, timeout: Duration) -> MqResult<Option<Message>> {
+        self.poll(timeout, |wait| {
+            let (head, data) = self.exchange(wait, 0)?;
+            self.message(head, data)
+        })
+    }
+
+    fn poll<T, F: FnMut(Option<Duration>) -> MqResult<Option<T>>>(&self, timeout: Duration, mut fetch: F) -> MqResult<Option<T>> {
+        let deadline = Instant::now() + timeout;
+        let long_poll = self.session.read().unwrap().config().long_poll;
+        let mut pause = POLL_MIN_PAUSE;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if let Some(found) = fetch(long_poll.then_some(remaining))? {
+                return Ok(Some(found));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            thread::sleep(pause.min(remaining));
+            pause = (pause * 2).min(POLL_MAX_PAUSE);
+        }
+    }
+
+    // Sends one fetch and reads its reply. A read can end early on a frame for another channel,
+    // so the reply is waited for until the read timeout, on top of the wait of a long poll.
+    fn exchange(&self, wait: Option<Duration>, count: u32) -> MqResult<(DataHead, Payload)> {
+        self.channel.status.check()?;
+        let deadline = {
+            let session = self.session.read().unwrap();
+            session.send(self.fetch_frame(&session, wait, count))?;
+            Instant::now() + wait.unwrap_or_default() + session.config().read_timeout
+        };
+        self.read_reply(deadline)
+    }
+
+    // A read comes back with Timeout when the frame it got was for another channel, so it is
+    // tried again until the deadline.
+    fn read_reply(&self, deadline: Instant) -> MqResult<(DataHead, Payload)> {
+        loop {
+            // the session lock is given up between reads so the wait does not hold up other users
+            match self.session.read().unwrap().read(&self.channel.status.name) {
+                Err(MqError::Timeout) if Instant::now() < deadline => continue,
+                result => return result,
+            }
+        }
+    }
+
+    // A confirmed push gets the channel's next sequence number, the others ask for no confirm.
+    fn send_push(&self, session: &Session, data: Vec<u8>, count: u32, confirmed: bool) -> MqResult<Option<u64>> {
+        let seq = if confirmed { self.channel.next_publish() } else { None };
+        let result = session.send(self.push_frame(session, data, count, seq));
+        match (result, seq) {
+            (Ok(()), seq) => Ok(seq),
+            (Err(err), Some(seq)) => {
+                self.channel.cancel_publish(seq);
+                Err(err)
+            }
+            (Err(err), None) => Err(err),
+        }
+    }
+
+    fn push_frame(&self, session: &Session, data: Vec<u8>, count: u32, seq: Option<u64>) -> Frame {
         let routing_mod = RoutingModFactory::new()
             .data_type(DataType::Message)
             .message_type(MessageType::Push)
             .routing_type(RoutingType::Direct)
             .build();
-
-        Ok(self.session.write().unwrap().send(
-            MessageFactory::new(self.host_name.clone(), self.channel_name.clone())
-                .routing_mod(routing_mod)
-                .routing_chain(self.routing_chain.clone())
-                .data(data)
-                .build()
-        )?)
+        session.factory(self.channel.status.host.clone(), self.channel.status.name.clone())
+            .routing_mod(routing_mod)
+            .routing_chain(self.routing_chain.clone())
+            .count(count)
+            .ack(if seq.is_some() { AckKind::Ack } else { AckKind::None })
+            .delivery_tag(seq.unwrap_or(0))
+            .data(data)
+            .build_frame()
     }
 
-    pub fn fetch(&self) -> Result<(Option<DataHead>, Option<Vec<u8>>), Box<dyn std::error::Error>> {
+    fn fetch_frame(&self, session: &Session, wait: Option<Duration>, count: u32) -> Frame {
         let routing_mod = RoutingModFactory::new()
             .data_type(DataType::Message)
             .message_type(MessageType::Fetch)
             .routing_type(RoutingType::Direct)
             .build();
+        session.factory(self.channel.status.host.clone(), self.channel.status.name.clone())
+            .routing_mod(routing_mod)
+            .routing_chain(self.routing_chain.clone())
+            .wait(wait)
+            .count(count)
+            .ack(match self.channel.ack_mode() {
+                AckMode::Auto => AckKind::None,
+                AckMode::Manual => AckKind::Ack,
+            })
+            .build_frame()
+    }
+
+    fn message(&self, head: DataHead, data: Payload) -> MqResult<Option<Message>> {
+        Ok(self.messages(head, data, false)?.into_iter().next())
+    }
 
-        let (head, data) = self.session.write().unwrap().send_and_read(
-            MessageFactory::new(self.host_name.clone(), self.channel_name.clone())
-                .routing_mod(routing_mod)
-                .routing_chain(self.routing_chain.clone())
-                .build(),
-            &self.channel_name
-        )?;
+    // only the reply to a fetch with a count, on a session with multi-message frames, is a batch,
+    // any other reply is a single message whatever its count says
+    fn messages(&self, head: DataHead, data: Payload, batched: bool) -> MqResult<Vec<Message>> {
+        let messages = match ErrorCode::from_errcode(head.errcode) {
+            None if batched => Message::unpack(head, data)?,
+            None => vec![Message::from_frame(head, data)],
+            Some(ErrorCode::NoItem) => vec![],
+            Some(code) => return Err(MqError::Broker(code)),
+        };
+        if self.channel.ack_mode() == AckMode::Auto {
+            return Ok(messages);
+        }
+        Ok(messages.into_iter().map(|message| message.tracked(&self.channel)).collect())
+    }
 
-        Ok((head, Some(*data)))
+    pub fn typed<T, C: MessageCodec<T>>(&self, codec: C) -> TypedQueue<T, C> {
+        TypedQueue::new(self.clone(), codec)
     }
 
-    pub fn push_string(&self, data: String) -> Result<(), Box<dyn std::error::Error>> {
-        self.push(data.into_bytes())
+    pub fn push_string(&self, data: String) -> MqResult<()> {
+        self.typed(Utf8Codec).push(&data)
     }
 
-    pub fn fetch_string(&self) -> Result<(Option<DataHead>, Option<String>), Box<dyn std::error::Error>> {
-        let (head, data) = self.fetch()?;
-        Ok((head, data.map(|data| String::from_utf8(data).unwrap())))
+    // auto acks only, see TypedQueue::fetch
+    pub fn fetch_string(&self) -> MqResult<Option<String>> {
+        self.typed(Utf8Codec).fetch()
     }
 
     pub fn fetch_simple(&self) -> FetchResult {
         match self.fetch() {
-            Ok((head, data)) =>{
-                if head.is_some() && data.is_some() {
-                    if head.unwrap().errcode == 0x0u16 {
-                        FetchResult::Success(data.unwrap())
-                    } else {
-                        FetchResult::FailedNoItem
-                    }
-                } else {
-                    FetchResult::FailedEmptyMessage
-                }
-            }
-            Err(err) => {
-                FetchResult::FailedError(err)
-            }
+            Ok(Some(message)) => FetchResult::Success(message.into_body()),
+            Ok(None) => FetchResult::FailedNoItem,
+            Err(err) => FetchResult::FailedError(err),
         }
     }
 
+    // consumes the queue on a background thread until the subscription is cancelled
+    pub fn subscribe<H>(&self, handler: H) -> Subscription
+    where
+        H: FnMut(Message) -> HandlerResult + Send + 'static,
+    {
+        self.subscribe_with(SubscriptionConfig::default(), handler)
+    }
+
+    pub fn subscribe_with<H>(&self, config: SubscriptionConfig, handler: H) -> Subscription
+    where
+        H: FnMut(Message) -> HandlerResult + Send + 'static,
+    {
+        Subscription::spawn(self.clone(), config, handler)
+    }
+
+    // blocks for the next message, see Messages for when it ends
+    pub fn iter(&self) -> Messages {
+        Messages::new(self.clone())
+    }
+
+    pub fn into_receiver(self) -> MessageReceiver {
+        self.into_iter().into_receiver()
+    }
+
+    // Everything sent through the returned sender is pushed to the queue. The thread behind it
+    // ends once every sender is dropped or a push fails, and reports how many messages it pushed.
+    pub fn into_sender(self) -> (Sender<Vec<u8>>, thread::JoinHandle<MqResult<u64>>) {
+        let (tx, rx) = channel::<Vec<u8>>();
+        let pusher = thread::spawn(move || {
+            let mut pushed = 0;
+            for data in rx {
+                self.push(data)?;
+                pushed += 1;
+            }
+            Ok(pushed)
+        });
+        (tx, pusher)
+    }
+
     pub fn fetch_simple_string(&self) -> FetchResultString {
         match self.fetch_simple() {
             FetchResult::Success(data) =>
@@ -98,9 +404,6 @@ impl Queue {
             FetchResult::FailedNoItem => {
                 FetchResultString::FailedNoItem
             }
-            FetchResult::FailedEmptyMessage => {
-                FetchResultString::FailedEmptyMessage
-            }
             FetchResult::FailedError(err) => {
                 FetchResultString::FailedError(err)
             }
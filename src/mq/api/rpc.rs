@@ -0,0 +1,208 @@
+use crate::mq::api::common::{ChannelApi, ChannelQueueApi};
+use crate::mq::api::message::{self, Message};
+use crate::mq::api::queue::Queue;
+use crate::mq::api::subscription::{Subscription, SubscriptionConfig, SubscriptionStats};
+use crate::mq::error::{MqError, MqResult};
+use crate::mq::io::channel::Channel;
+use crate::mq::io::factory::Routing;
+use crate::mq::routing::chain::RoutingChain;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// tells the reply queues of clients in one process apart
+static NEXT_CLIENT: AtomicU64 = AtomicU64::new(1);
+
+// Longest a call waits on the reply queue before looking for a reply another caller handed over,
+// a caller blocked in a fetch does not notice one arriving.
+const REPLY_STEP: Duration = Duration::from_millis(10);
+
+pub type RpcHandlerResult = Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+// Carried in front of the body of requests and replies, as packed by message::pack:
+// the correlation id, the reply-to queue (empty on a reply) and a status byte.
+struct Envelope {
+    correlation_id: String,
+    reply_to: String,
+    // the handler failed, the body holds its error
+    failed: bool,
+}
+
+impl Envelope {
+    fn wrap(&self, body: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(9 + self.correlation_id.len() + self.reply_to.len() + body.len());
+        message::pack(&mut data, self.correlation_id.as_bytes());
+        message::pack(&mut data, self.reply_to.as_bytes());
+        data.push(self.failed as u8);
+        data.extend_from_slice(body);
+        data
+    }
+
+    // the envelope and the message without it
+    fn unwrap(message: Message) -> MqResult<(Envelope, Message)> {
+        let (correlation_id, offset) = field(message.body(), 0)?;
+        let (reply_to, offset) = field(message.body(), offset)?;
+        let failed = match message.body().get(offset) {
+            Some(0) => false,
+            Some(1) => true,
+            _ => return Err(MqError::Protocol(String::from("rpc envelope without a valid status"))),
+        };
+        let envelope = Envelope {
+            correlation_id,
+            reply_to,
+            failed,
+        };
+        Ok((envelope, message.skip(offset + 1)))
+    }
+}
+
+fn field(body: &[u8], offset: usize) -> MqResult<(String, usize)> {
+    let len = body.get(offset..offset + 4)
+        .map(|len| u32::from_le_bytes(<[u8; 4]>::try_from(len).unwrap()) as usize)
+        .filter(|len| offset + 4 + len <= body.len())
+        .ok_or_else(|| MqError::Protocol(String::from("rpc envelope ends early")))?;
+    Ok((String::from_utf8(body[offset + 4..offset + 4 + len].to_vec())?, offset + 4 + len))
+}
+
+// replies go straight to the queue named by the request
+fn reply_chain(queue_name: String) -> RoutingChain {
+    RoutingChain::new([Routing::Stop, Routing::Stop, Routing::Stop], queue_name)
+}
+
+// Sends requests to a queue and waits for the matching replies on a reply queue of its own,
+// declared on `channel` and dropped again with the client. Calls may be made from several
+// threads at once, a reply fetched by the wrong caller is handed over.
+pub struct RpcClient {
+    channel: Arc<RwLock<Channel>>,
+    requests: Queue,
+    replies: Queue,
+    reply_to: String,
+    next_id: AtomicU64,
+    // replies fetched for a call other than the one that fetched them
+    stray: Mutex<HashMap<String, (Envelope, Message)>>,
+    // calls waiting for their reply, whatever else arrives is a late reply to a call that gave up
+    waiting: Mutex<Vec<String>>,
+}
+
+impl RpcClient {
+    pub fn new(channel: Arc<RwLock<Channel>>, request_chain: RoutingChain) -> MqResult<RpcClient> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.subsec_nanos());
+        let reply_to = format!("rpc.reply.{}.{nanos}.{}", std::process::id(), NEXT_CLIENT.fetch_add(1, Ordering::SeqCst));
+        let (requests, replies) = {
+            let mut channel = channel.write().unwrap();
+            channel.create_queue(reply_to.clone(), reply_chain(reply_to.clone()))?;
+            (channel.get_queue(request_chain)?, channel.get_queue(reply_chain(reply_to.clone()))?)
+        };
+        Ok(RpcClient {
+            channel,
+            requests,
+            replies,
+            reply_to,
+            next_id: AtomicU64::new(1),
+            stray: Mutex::new(HashMap::new()),
+            waiting: Mutex::new(vec![]),
+        })
+    }
+
+    pub fn reply_to(&self) -> &String {
+        &self.reply_to
+    }
+
+    // The reply, or MqError::Rpc if the server's handler failed. A reply arriving after
+    // `timeout` is dropped once it turns up.
+    pub fn call(&self, body: Vec<u8>, timeout: Duration) -> MqResult<Message> {
+        let correlation_id = self.next_id.fetch_add(1, Ordering::SeqCst).to_string();
+        let envelope = Envelope {
+            correlation_id: correlation_id.clone(),
+            reply_to: self.reply_to.clone(),
+            failed: false,
+        };
+        self.waiting.lock().unwrap().push(correlation_id.clone());
+        let reply = self.requests.push(envelope.wrap(&body)).and_then(|_| self.wait(&correlation_id, timeout));
+        self.waiting.lock().unwrap().retain(|id| *id != correlation_id);
+        let (envelope, reply) = reply?;
+        if envelope.failed {
+            return Err(MqError::Rpc(reply.body_string()?));
+        }
+        Ok(reply)
+    }
+
+    fn wait(&self, correlation_id: &String, timeout: Duration) -> MqResult<(Envelope, Message)> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(reply) = self.stray.lock().unwrap().remove(correlation_id) {
+                return Ok(reply);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(MqError::Timeout);
+            }
+            let Some(reply) = self.replies.fetch_timeout(remaining.min(REPLY_STEP))? else {
+                continue;
+            };
+            let (envelope, reply) = Envelope::unwrap(reply)?;
+            if envelope.correlation_id == *correlation_id {
+                return Ok((envelope, reply));
+            }
+            if self.waiting.lock().unwrap().contains(&envelope.correlation_id) {
+                self.stray.lock().unwrap().insert(envelope.correlation_id.clone(), (envelope, reply));
+            }
+        }
+    }
+}
+
+impl Drop for RpcClient {
+    fn drop(&mut self) {
+        let _ = self.channel.write().unwrap().drop_queue(self.reply_to.clone(), reply_chain(self.reply_to.clone()));
+    }
+}
+
+// Consumes a queue of requests on a subscription and pushes what the handler returns to the
+// reply-to queue of each request. Stops once dropped.
+pub struct RpcServer {
+    subscription: Subscription,
+}
+
+impl RpcServer {
+    pub fn serve<H>(queue: Queue, handler: H) -> RpcServer
+    where
+        H: FnMut(&Message) -> RpcHandlerResult + Send + 'static,
+    {
+        RpcServer::serve_with(queue, SubscriptionConfig::default(), handler)
+    }
+
+    // Requests that cannot be read count as handler errors of the subscription. A request fetched
+    // with manual acks is acked once its reply is pushed.
+    pub fn serve_with<H>(queue: Queue, config: SubscriptionConfig, mut handler: H) -> RpcServer
+    where
+        H: FnMut(&Message) -> RpcHandlerResult + Send + 'static,
+    {
+        let replies = queue.clone();
+        let subscription = queue.subscribe_with(config, move |request| {
+            let (envelope, request) = Envelope::unwrap(request)?;
+            let (failed, body) = match handler(&request) {
+                Ok(body) => (false, body),
+                Err(err) => (true, err.to_string().into_bytes()),
+            };
+            let reply = Envelope {
+                correlation_id: envelope.correlation_id,
+                reply_to: String::new(),
+                failed,
+            };
+            replies.for_routing(reply_chain(envelope.reply_to)).push(reply.wrap(&body))?;
+            request.ack()?;
+            Ok(())
+        });
+        RpcServer { subscription }
+    }
+
+    pub fn stats(&self) -> SubscriptionStats {
+        self.subscription.stats()
+    }
+
+    pub fn stop(&mut self) {
+        self.subscription.cancel();
+    }
+}
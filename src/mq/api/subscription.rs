@@ -0,0 +1,256 @@
+use crate::mq::api::message::Message;
+use crate::mq::api::queue::Queue;
+use crate::mq::error::MqError;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub type HandlerResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+#[derive(Debug)]
+pub enum SubscriptionError {
+    Fetch(MqError),
+    Handler(Box<dyn Error + Send + Sync>),
+}
+
+impl Display for SubscriptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubscriptionError::Fetch(err) => write!(f, "fetch failed: {err}"),
+            SubscriptionError::Handler(err) => write!(f, "handler failed: {err}"),
+        }
+    }
+}
+
+impl Error for SubscriptionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorAction {
+    Continue,
+    Pause,
+    Cancel,
+}
+
+#[derive(Clone)]
+pub enum ErrorPolicy {
+    // handler errors are ignored, fetch errors back off like an empty queue
+    Continue,
+    Cancel,
+    Callback(Arc<dyn Fn(&SubscriptionError) -> ErrorAction + Send + Sync>),
+}
+
+impl ErrorPolicy {
+    pub fn callback<F: Fn(&SubscriptionError) -> ErrorAction + Send + Sync + 'static>(callback: F) -> ErrorPolicy {
+        ErrorPolicy::Callback(Arc::new(callback))
+    }
+
+    fn action(&self, err: &SubscriptionError) -> ErrorAction {
+        match self {
+            ErrorPolicy::Continue => ErrorAction::Continue,
+            ErrorPolicy::Cancel => ErrorAction::Cancel,
+            ErrorPolicy::Callback(callback) => callback(err),
+        }
+    }
+}
+
+impl Debug for ErrorPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorPolicy::Continue => write!(f, "Continue"),
+            ErrorPolicy::Cancel => write!(f, "Cancel"),
+            ErrorPolicy::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriptionConfig {
+    // wait after the first empty fetch, doubled (by `multiplier`) for every empty fetch after it
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    pub error_policy: ErrorPolicy,
+}
+
+impl SubscriptionConfig {
+    pub fn new() -> SubscriptionConfig {
+        SubscriptionConfig {
+            min_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            error_policy: ErrorPolicy::Continue,
+        }
+    }
+
+    pub fn min_backoff(mut self, min_backoff: Duration) -> SubscriptionConfig {
+        self.min_backoff = min_backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> SubscriptionConfig {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> SubscriptionConfig {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> SubscriptionConfig {
+        self.error_policy = error_policy;
+        self
+    }
+
+    fn next_backoff(&self, backoff: Option<Duration>) -> Duration {
+        match backoff {
+            Some(backoff) => backoff.mul_f64(self.multiplier).min(self.max_backoff),
+            None => self.min_backoff,
+        }
+    }
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        SubscriptionConfig::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscriptionState {
+    Running,
+    Paused,
+    // cancelled by the caller, by the error policy or because the channel closed
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubscriptionStats {
+    pub delivered: u64,
+    pub handler_errors: u64,
+    pub fetch_errors: u64,
+    pub empty_fetches: u64,
+}
+
+struct Shared {
+    state: Mutex<SubscriptionState>,
+    changed: Condvar,
+    stats: Mutex<SubscriptionStats>,
+}
+
+impl Shared {
+    fn set(&self, state: SubscriptionState) {
+        let mut current = self.state.lock().unwrap();
+        // a cancelled subscription stays cancelled
+        if *current != SubscriptionState::Cancelled {
+            *current = state;
+        }
+        self.changed.notify_all();
+    }
+
+    // waits out `timeout` or a pause, false once the subscription is cancelled
+    fn wait(&self, timeout: Duration) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if *state == SubscriptionState::Running && !timeout.is_zero() {
+            state = self.changed.wait_timeout(state, timeout).unwrap().0;
+        }
+        while *state == SubscriptionState::Paused {
+            state = self.changed.wait(state).unwrap();
+        }
+        *state == SubscriptionState::Running
+    }
+}
+
+// A consumer thread fetching from a queue and handing every message to a handler.
+// The thread stops once the subscription is cancelled or dropped.
+pub struct Subscription {
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Subscription {
+    pub fn spawn<H>(queue: Queue, config: SubscriptionConfig, mut handler: H) -> Subscription
+    where
+        H: FnMut(Message) -> HandlerResult + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(SubscriptionState::Running),
+            changed: Condvar::new(),
+            stats: Mutex::new(SubscriptionStats::default()),
+        });
+        let worker = shared.clone();
+        let thread = thread::spawn(move || {
+            let mut backoff = None;
+            while worker.wait(backoff.unwrap_or(Duration::ZERO)) {
+                let err = match queue.fetch() {
+                    Ok(Some(message)) => {
+                        backoff = None;
+                        worker.stats.lock().unwrap().delivered += 1;
+                        match handler(message) {
+                            Ok(()) => continue,
+                            Err(err) => {
+                                worker.stats.lock().unwrap().handler_errors += 1;
+                                SubscriptionError::Handler(err)
+                            }
+                        }
+                    }
+                    Ok(None) => {
+                        backoff = Some(config.next_backoff(backoff));
+                        worker.stats.lock().unwrap().empty_fetches += 1;
+                        continue;
+                    }
+                    // nothing will ever arrive on a closed channel
+                    Err(MqError::ChannelClosed(_)) => break,
+                    Err(err) => {
+                        backoff = Some(config.next_backoff(backoff));
+                        worker.stats.lock().unwrap().fetch_errors += 1;
+                        SubscriptionError::Fetch(err)
+                    }
+                };
+                match config.error_policy.action(&err) {
+                    ErrorAction::Continue => {}
+                    ErrorAction::Pause => worker.set(SubscriptionState::Paused),
+                    ErrorAction::Cancel => break,
+                }
+            }
+            worker.set(SubscriptionState::Cancelled);
+        });
+        Subscription {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn state(&self) -> SubscriptionState {
+        *self.shared.state.lock().unwrap()
+    }
+
+    pub fn stats(&self) -> SubscriptionStats {
+        self.shared.stats.lock().unwrap().clone()
+    }
+
+    // a message that is already being fetched is still handed to the handler
+    pub fn pause(&self) {
+        self.shared.set(SubscriptionState::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.shared.set(SubscriptionState::Running);
+    }
+
+    // stops the consumer thread and waits for the handler to return
+    pub fn cancel(&mut self) {
+        self.shared.set(SubscriptionState::Cancelled);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
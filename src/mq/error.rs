@@ -0,0 +1,141 @@
+use crate::mq::io::auth::AuthError;
+use crate::mq::io::channel::ChannelClosed;
+use crate::mq::io::config::ConfigError;
+use crate::mq::io::confirm::Confirmation;
+use crate::mq::io::heartbeat::Disconnected;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+
+pub type MqResult<T> = Result<T, MqError>;
+
+// errcode of a reply frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    NoItem,
+    Other(u16),
+}
+
+impl ErrorCode {
+    pub fn from_errcode(errcode: u16) -> Option<ErrorCode> {
+        match errcode {
+            0 => None,
+            0xf => Some(ErrorCode::NoItem),
+            code => Some(ErrorCode::Other(code)),
+        }
+    }
+
+    pub fn errcode(&self) -> u16 {
+        match self {
+            ErrorCode::NoItem => 0xf,
+            ErrorCode::Other(code) => *code,
+        }
+    }
+}
+
+// Everything the client can fail with. Timeout and a connection reset are worth retrying,
+// the other variants mostly are not: Disconnected means the session already gave up reconnecting.
+#[derive(Debug)]
+pub enum MqError {
+    Io(std::io::Error),
+    // nothing arrived for the channel within the read timeout
+    Timeout,
+    Protocol(String),
+    Broker(ErrorCode),
+    ChannelClosed(ChannelClosed),
+    Disconnected(String),
+    Auth(AuthError),
+    // frames for the channel were dropped under OverflowPolicy::Error
+    CacheOverflow(String),
+    Validation(String),
+    // the broker nacked a publish in confirm mode, or its confirm was lost
+    Unconfirmed { seq: u64, confirmation: Confirmation },
+    // the handler of an rpc server failed on the request, with its error
+    Rpc(String),
+    // a value could not be encoded, or a body decoded, see MessageCodec
+    Codec(String),
+}
+
+impl MqError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            MqError::Timeout => true,
+            MqError::Io(err) => matches!(err.kind(), ErrorKind::Interrupted | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe),
+            _ => false,
+        }
+    }
+}
+
+impl Display for MqError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MqError::Io(err) => write!(f, "io error: {err}"),
+            MqError::Timeout => write!(f, "timed out waiting for the broker"),
+            MqError::Protocol(reason) => write!(f, "protocol error: {reason}"),
+            MqError::Broker(code) => write!(f, "broker replied with error code {:#x}", code.errcode()),
+            MqError::ChannelClosed(err) => write!(f, "{err}"),
+            MqError::Disconnected(reason) => write!(f, "disconnected: {reason}"),
+            MqError::Auth(err) => write!(f, "{err}"),
+            MqError::CacheOverflow(channel) => write!(f, "cache overflow on channel {channel}, frames were dropped"),
+            MqError::Validation(reason) => write!(f, "invalid argument: {reason}"),
+            MqError::Unconfirmed { seq, confirmation } => write!(f, "publish {seq} was not confirmed: {confirmation:?}"),
+            MqError::Rpc(reason) => write!(f, "rpc handler failed: {reason}"),
+            MqError::Codec(reason) => write!(f, "codec error: {reason}"),
+        }
+    }
+}
+
+impl Error for MqError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MqError::Io(err) => Some(err),
+            MqError::ChannelClosed(err) => Some(err),
+            MqError::Auth(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// io errors carry the session's own error types inside, those get their own variant back
+impl From<std::io::Error> for MqError {
+    fn from(err: std::io::Error) -> Self {
+        if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+            return MqError::Timeout;
+        }
+        if let Some(auth) = AuthError::find(&err) {
+            return MqError::Auth(auth.clone());
+        }
+        if let Some(disconnected) = err.get_ref().and_then(|inner| inner.downcast_ref::<Disconnected>()) {
+            return MqError::Disconnected(disconnected.reason.clone());
+        }
+        match err.kind() {
+            ErrorKind::InvalidData => MqError::Protocol(err.to_string()),
+            ErrorKind::InvalidInput => MqError::Validation(err.to_string()),
+            _ => MqError::Io(err),
+        }
+    }
+}
+
+impl From<ChannelClosed> for MqError {
+    fn from(err: ChannelClosed) -> Self {
+        MqError::ChannelClosed(err)
+    }
+}
+
+impl From<AuthError> for MqError {
+    fn from(err: AuthError) -> Self {
+        MqError::Auth(err)
+    }
+}
+
+impl From<ConfigError> for MqError {
+    fn from(err: ConfigError) -> Self {
+        MqError::Validation(err.to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for MqError {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        MqError::Protocol(err.to_string())
+    }
+}
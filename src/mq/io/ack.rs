@@ -0,0 +1,81 @@
+use crate::mq::io::events::{EventBus, SessionEvent};
+use crate::mq::io::factory::{AckKind, DataType, MessageFactory, MessageType, RoutingModFactory};
+use crate::mq::io::frame::Frame;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AckMode {
+    // the broker forgets a message as soon as it is fetched
+    Auto,
+    // the broker keeps a fetched message until it is acked, nacked or rejected
+    Manual,
+}
+
+// Deliveries fetched with AckMode::Manual that were not settled yet, per channel.
+// Shared by the session and the handles of its channels.
+#[derive(Clone, Default)]
+pub struct UnackedTracker {
+    deliveries: Arc<Mutex<HashMap<String, BTreeSet<u64>>>>,
+}
+
+impl UnackedTracker {
+    pub fn new() -> UnackedTracker {
+        UnackedTracker::default()
+    }
+
+    pub fn track(&self, channel: &str, tag: u64) {
+        self.deliveries.lock().unwrap().entry(channel.to_string()).or_default().insert(tag);
+    }
+
+    // false if the delivery is not outstanding on the channel
+    pub fn settle(&self, channel: &str, tag: u64) -> bool {
+        self.deliveries.lock().unwrap().get_mut(channel).is_some_and(|tags| tags.remove(&tag))
+    }
+
+    pub fn is_unacked(&self, channel: &str, tag: u64) -> bool {
+        self.deliveries.lock().unwrap().get(channel).is_some_and(|tags| tags.contains(&tag))
+    }
+
+    pub fn unacked(&self, channel: &str) -> usize {
+        self.deliveries.lock().unwrap().get(channel).map_or(0, BTreeSet::len)
+    }
+
+    // Forgets what is outstanding on the channel, the broker redelivers it.
+    pub fn release(&self, channel: &str, events: &EventBus) -> Vec<u64> {
+        let tags = self.deliveries.lock().unwrap().remove(channel).map(Vec::from_iter).unwrap_or_default();
+        if !tags.is_empty() {
+            events.emit(SessionEvent::Requeued { channel: channel.to_string(), count: tags.len() });
+        }
+        tags
+    }
+
+    // requeues whatever a closing channel still holds, the frames go out ahead of CLOSE-CH
+    pub fn requeue_frames(&self, host: &str, channel: &str, slice_size: Option<u32>, events: &EventBus) -> Vec<Frame> {
+        self.release(channel, events)
+            .into_iter()
+            .map(|tag| ack_frame(host, channel, slice_size, tag, AckKind::Requeue))
+            .collect()
+    }
+
+    // The connection the deliveries were fetched on is gone, and the broker requeues them itself.
+    pub fn connection_lost(&self, events: &EventBus) {
+        let lost = std::mem::take(&mut *self.deliveries.lock().unwrap());
+        for (channel, tags) in lost.into_iter().filter(|(_, tags)| !tags.is_empty()) {
+            events.emit(SessionEvent::Requeued { channel, count: tags.len() });
+        }
+    }
+}
+
+pub fn ack_frame(host: &str, channel: &str, slice_size: Option<u32>, tag: u64, kind: AckKind) -> Frame {
+    let routing_mod = RoutingModFactory::new()
+        .data_type(DataType::Message)
+        .message_type(MessageType::Ack)
+        .build();
+    MessageFactory::new(host.to_string(), channel.to_string())
+        .slice_size(slice_size)
+        .routing_mod(routing_mod)
+        .ack(kind)
+        .delivery_tag(tag)
+        .build_frame()
+}
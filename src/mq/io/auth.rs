@@ -0,0 +1,132 @@
+use crate::mq::io::factory::{Command, MessageFactory};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+
+#[derive(Clone, PartialEq)]
+pub enum Credentials {
+    Password { username: String, password: String },
+    Token(String),
+}
+
+impl Credentials {
+    pub fn password(username: String, password: String) -> Credentials {
+        Credentials::Password { username, password }
+    }
+
+    pub fn token(token: String) -> Credentials {
+        Credentials::Token(token)
+    }
+
+    // payload of the AUTH frame: a kind byte, then every field as a u16 LE length followed by its bytes
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, fields) = match self {
+            Credentials::Password { username, password } => (0u8, vec![username, password]),
+            Credentials::Token(token) => (1u8, vec![token]),
+        };
+        let mut data = vec![kind];
+        for field in fields {
+            let bytes = &field.as_bytes()[..field.len().min(u16::MAX as usize)];
+            data.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+            data.extend_from_slice(bytes);
+        }
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Credentials> {
+        let (kind, mut rest) = data.split_first()?;
+        let mut fields = vec![];
+        for _ in 0..if *kind == 0 { 2 } else { 1 } {
+            let len = u16::from_le_bytes(<[u8; 2]>::try_from(rest.get(0..2)?).ok()?) as usize;
+            fields.push(String::from_utf8(rest.get(2..2 + len)?.to_vec()).ok()?);
+            rest = &rest[2 + len..];
+        }
+        match kind {
+            0 => Some(Credentials::password(fields.remove(0), fields.remove(0))),
+            1 => Some(Credentials::token(fields.remove(0))),
+            _ => None,
+        }
+    }
+
+    pub fn frame(&self, host: String) -> Vec<u8> {
+        MessageFactory::new(host, String::new())
+            .command(Command::Auth)
+            .data(self.encode())
+            .build()
+    }
+}
+
+// keeps secrets out of logs and panics
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::Password { username, .. } => write!(f, "Password {{ username: {username:?}, password: \"***\" }}"),
+            Credentials::Token(_) => write!(f, "Token(\"***\")"),
+        }
+    }
+}
+
+// Why the broker refused to log the session in, decoded from the errcode of its AUTH reply.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    InvalidCredentials,
+    TokenExpired,
+    // the credentials are fine but may not use this virtual host
+    Forbidden,
+    // the broker wants credentials but the session has none
+    Required,
+    NoResponse,
+    Other(u16),
+}
+
+impl AuthError {
+    pub fn from_errcode(errcode: u16) -> Option<AuthError> {
+        match errcode {
+            0 => None,
+            1 => Some(AuthError::InvalidCredentials),
+            2 => Some(AuthError::TokenExpired),
+            3 => Some(AuthError::Forbidden),
+            4 => Some(AuthError::Required),
+            code => Some(AuthError::Other(code)),
+        }
+    }
+
+    pub fn errcode(&self) -> u16 {
+        match self {
+            AuthError::InvalidCredentials => 1,
+            AuthError::TokenExpired => 2,
+            AuthError::Forbidden => 3,
+            AuthError::Required => 4,
+            AuthError::NoResponse => 0xffff,
+            AuthError::Other(code) => *code,
+        }
+    }
+
+    pub fn io(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, self)
+    }
+
+    // finds an AuthError in an error returned by the session, directly or wrapped in an io::Error
+    pub fn find<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a AuthError> {
+        if let Some(auth) = err.downcast_ref::<AuthError>() {
+            return Some(auth);
+        }
+        err.downcast_ref::<std::io::Error>()
+            .and_then(|err| err.get_ref())
+            .and_then(|inner| inner.downcast_ref::<AuthError>())
+    }
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "authentication failed: invalid credentials"),
+            AuthError::TokenExpired => write!(f, "authentication failed: token expired"),
+            AuthError::Forbidden => write!(f, "authentication failed: access to the virtual host is forbidden"),
+            AuthError::Required => write!(f, "authentication failed: the broker requires credentials"),
+            AuthError::NoResponse => write!(f, "authentication failed: broker did not answer"),
+            AuthError::Other(code) => write!(f, "authentication failed with code {code}"),
+        }
+    }
+}
+
+impl Error for AuthError {}
@@ -0,0 +1,130 @@
+use crate::mq::io::pool::Payload;
+use crate::mq::protocol::proto::DataHead;
+use std::collections::VecDeque;
+
+// What happens to a frame that arrives for a channel whose cache is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    // the reading thread waits until the channel's consumer makes room
+    Block,
+    DropOldest,
+    DropNewest,
+    // the frame is dropped and the channel's next read fails
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheLimit {
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl CacheLimit {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> CacheLimit {
+        CacheLimit {
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    pub fn unbounded() -> CacheLimit {
+        CacheLimit::new(usize::MAX, OverflowPolicy::Block)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheStats {
+    pub len: usize,
+    pub capacity: usize,
+    pub dropped_oldest: u64,
+    pub dropped_newest: u64,
+    pub overflow_errors: u64,
+    // number of frames that had to wait for room
+    pub blocked: u64,
+}
+
+// A frame kept with its head so the channel still sees where it came from.
+pub type CachedFrame = (DataHead, Payload);
+
+// Frames read off the connection on behalf of a channel that was not the one reading.
+pub struct ChannelCache {
+    queue: VecDeque<CachedFrame>,
+    limit: CacheLimit,
+    stats: CacheStats,
+    overflowed: bool,
+}
+
+impl ChannelCache {
+    pub fn new(limit: CacheLimit) -> ChannelCache {
+        ChannelCache {
+            queue: VecDeque::new(),
+            limit,
+            stats: CacheStats::default(),
+            overflowed: false,
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: CacheLimit) {
+        self.limit = limit;
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.limit.policy
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.queue.len() >= self.limit.capacity
+    }
+
+    // hands the frame back only if the policy is Block and there is no room
+    pub fn push(&mut self, frame: CachedFrame) -> Option<CachedFrame> {
+        if !self.is_full() {
+            self.queue.push_back(frame);
+            return None;
+        }
+        match self.limit.policy {
+            OverflowPolicy::Block => {
+                return Some(frame);
+            }
+            OverflowPolicy::DropOldest => {
+                self.queue.pop_front();
+                self.queue.push_back(frame);
+                self.stats.dropped_oldest += 1;
+            }
+            OverflowPolicy::DropNewest => {
+                self.stats.dropped_newest += 1;
+            }
+            OverflowPolicy::Error => {
+                self.stats.overflow_errors += 1;
+                self.overflowed = true;
+            }
+        }
+        None
+    }
+
+    pub fn record_blocked(&mut self) {
+        self.stats.blocked += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<CachedFrame> {
+        self.queue.pop_front()
+    }
+
+    // true once after frames were dropped under the Error policy
+    pub fn take_overflow(&mut self) -> bool {
+        std::mem::take(&mut self.overflowed)
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.overflowed = false;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            len: self.queue.len(),
+            capacity: self.limit.capacity,
+            ..self.stats.clone()
+        }
+    }
+}
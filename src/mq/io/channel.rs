@@ -1,123 +1,354 @@
 use crate::mq::api::common::{ChannelApi, ChannelQueueApi};
-use crate::mq::io::factory::{Command, CommandType, DataType, MessageFactory, RoutingModFactory, RoutingType};
+use crate::mq::error::{MqError, MqResult};
+use crate::mq::io::ack::{self, AckMode, UnackedTracker};
+use crate::mq::io::confirm::{ConfirmTracker, PendingConfirms};
+use crate::mq::io::events::EventBus;
+use crate::mq::io::factory::{AckKind, Command, CommandType, MessageFactory};
+use crate::mq::io::pool::Payload;
 use crate::mq::io::session::Session;
+use crate::mq::io::topology::Declaration;
+use crate::mq::io::writer::Writer;
 use crate::mq::protocol::proto::DataHead;
 use crate::mq::routing::chain::RoutingChain;
 use std::error::Error;
-use std::sync::{Arc, RwLock};
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex, RwLock};
 use crate::mq::api::queue::Queue;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelState {
+    // re-opening after a reconnect, the topology is being replayed
+    Opening,
+    Open,
+    // CLOSE-CH was sent, the broker has not confirmed it yet
+    Closing,
+    Closed,
+    // the session lost its connection for good
+    Failed,
+}
+
+impl ChannelState {
+    pub fn is_usable(&self) -> bool {
+        matches!(self, ChannelState::Opening | ChannelState::Open)
+    }
+}
+
+// The state of one channel, shared by the channel, its queues and the session.
+#[derive(Debug, Clone)]
+pub struct ChannelStatus {
+    pub host: String,
+    pub name: String,
+    state: Arc<Mutex<ChannelState>>,
+}
+
+impl ChannelStatus {
+    pub fn new(host: String, name: String) -> ChannelStatus {
+        ChannelStatus {
+            host,
+            name,
+            state: Arc::new(Mutex::new(ChannelState::Open)),
+        }
+    }
+
+    pub fn get(&self) -> ChannelState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn set(&self, state: ChannelState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    // moves from `from` to `to` if the channel is in one of the `from` states
+    pub fn transition(&self, from: &[ChannelState], to: ChannelState) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if from.contains(&state) {
+            *state = to;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn check(&self) -> Result<(), ChannelClosed> {
+        let state = self.get();
+        if state.is_usable() {
+            Ok(())
+        } else {
+            Err(ChannelClosed {
+                channel: self.name.clone(),
+                state,
+            })
+        }
+    }
+
+    // Open or Opening to Closing, true if the caller should send CLOSE-CH
+    pub fn begin_close(&self) -> bool {
+        self.transition(&[ChannelState::Opening, ChannelState::Open], ChannelState::Closing)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelClosed {
+    pub channel: String,
+    pub state: ChannelState,
+}
+
+impl Display for ChannelClosed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "channel {} is {:?}", self.channel, self.state)
+    }
+}
+
+impl Error for ChannelClosed {}
+
+// Shared by a channel and its queues, the channel is closed once the last of them is dropped.
+pub struct ChannelHandle {
+    pub status: ChannelStatus,
+    // lets Drop send CLOSE-CH without taking the session lock
+    writer: Writer,
+    slice_size: Option<u32>,
+    ack_mode: Mutex<AckMode>,
+    // messages a consumer may hold, buffered or unacked, 0 for no prefetching
+    prefetch: Mutex<usize>,
+    unacked: UnackedTracker,
+    confirms: ConfirmTracker,
+    events: EventBus,
+}
+
+impl ChannelHandle {
+    pub fn new(status: ChannelStatus, writer: Writer, slice_size: Option<u32>, unacked: UnackedTracker, confirms: ConfirmTracker, events: EventBus) -> ChannelHandle {
+        ChannelHandle {
+            status,
+            writer,
+            slice_size,
+            ack_mode: Mutex::new(AckMode::Auto),
+            prefetch: Mutex::new(0),
+            unacked,
+            confirms,
+            events,
+        }
+    }
+
+    pub fn ack_mode(&self) -> AckMode {
+        *self.ack_mode.lock().unwrap()
+    }
+
+    pub fn set_ack_mode(&self, ack_mode: AckMode) {
+        *self.ack_mode.lock().unwrap() = ack_mode;
+    }
+
+    pub fn prefetch(&self) -> usize {
+        *self.prefetch.lock().unwrap()
+    }
+
+    pub fn set_prefetch(&self, prefetch: usize) {
+        *self.prefetch.lock().unwrap() = prefetch;
+    }
+
+    pub fn track(&self, tag: u64) {
+        self.unacked.track(&self.status.name, tag);
+    }
+
+    pub fn unacked(&self) -> usize {
+        self.unacked.unacked(&self.status.name)
+    }
+
+    pub fn is_unacked(&self, tag: u64) -> bool {
+        self.unacked.is_unacked(&self.status.name, tag)
+    }
+
+    // once enabled, the broker confirms every publish on the channel that asks for it
+    pub fn enable_confirms(&self) {
+        self.confirms.enable(&self.status.name);
+    }
+
+    pub fn confirms_enabled(&self) -> bool {
+        self.confirms.is_enabled(&self.status.name)
+    }
+
+    // the sequence number for the next publish, None outside of confirm mode
+    pub fn next_publish(&self) -> Option<u64> {
+        self.confirms.next(&self.status.name)
+    }
+
+    pub fn cancel_publish(&self, seq: u64) {
+        self.confirms.cancel(&self.status.name, seq);
+    }
+
+    pub fn pending_confirms(&self, session: Arc<RwLock<Session>>) -> PendingConfirms {
+        PendingConfirms::new(self.status.name.clone(), self.confirms.clone(), session)
+    }
+
+    pub fn settle(&self, tag: u64, kind: AckKind) -> MqResult<()> {
+        self.status.check()?;
+        if !self.unacked.is_unacked(&self.status.name, tag) {
+            return Err(MqError::Validation(format!("delivery {tag} is not outstanding on channel {}", self.status.name)));
+        }
+        let data = ack::ack_frame(&self.status.host, &self.status.name, self.slice_size, tag, kind);
+        self.writer.write(data).map_err(|(err, _)| MqError::from(err))?;
+        // only now, a delivery whose ack did not go out is still requeued when the channel closes
+        self.unacked.settle(&self.status.name, tag);
+        Ok(())
+    }
+
+    // requeues unsettled deliveries and sends CLOSE-CH, unless the channel is closing already
+    pub fn close(&self) -> bool {
+        if !self.status.begin_close() {
+            return false;
+        }
+        for data in self.unacked.requeue_frames(&self.status.host, &self.status.name, self.slice_size, &self.events) {
+            let _ = self.writer.write_nowait(data);
+        }
+        let data = MessageFactory::new(self.status.host.clone(), self.status.name.clone())
+            .slice_size(self.slice_size)
+            .command(Command::CloseChannel)
+            .build_frame();
+        let _ = self.writer.write_nowait(data);
+        true
+    }
+}
+
+impl Drop for ChannelHandle {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
 pub struct Channel {
     host_name: String,
     name: String,
-    closed: bool,
+    handle: Arc<ChannelHandle>,
+    status: ChannelStatus,
     session: Arc<RwLock<Session>>,
 }
 
 impl Channel {
-    pub fn new(host_name: String, name: String, session: Arc<RwLock<Session>>) -> Channel {
+    pub fn new(handle: ChannelHandle, session: Arc<RwLock<Session>>) -> Channel {
         Channel {
-            host_name,
-            name,
-            closed: false,
+            host_name: handle.status.host.clone(),
+            name: handle.status.name.clone(),
+            status: handle.status.clone(),
+            handle: Arc::new(handle),
             session,
         }
     }
 
+    pub fn state(&self) -> ChannelState {
+        self.status.get()
+    }
+
     pub fn is_closed(&self) -> bool {
-        self.closed
+        !self.state().is_usable()
+    }
+
+    pub fn host_name(&self) -> &String {
+        &self.host_name
     }
 
+    pub fn name(&self) -> &String {
+        &self.name
+    }
 
     pub fn get_factory(&self) -> MessageFactory {
-        MessageFactory::new(self.host_name.clone(), self.name.clone())
+        self.session.read().unwrap().factory(self.host_name.clone(), self.name.clone())
     }
 
+    // sends CLOSE-CH, the channel is Closed once the broker confirms
     pub fn close(&mut self) {
-        let factory = self.get_factory();
-        let data = factory.command(Command::CloseChannel).build();
+        self.handle.close();
+    }
 
-        let _ = self.send(data);
-        self.closed = true;
+    // applies to the channel's queues as well, to messages they fetch from now on
+    pub fn set_ack_mode(&mut self, ack_mode: AckMode) {
+        self.handle.set_ack_mode(ack_mode);
     }
 
-    pub fn send(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
-        Ok(self.session.write().unwrap().send(data)?)
+    pub fn ack_mode(&self) -> AckMode {
+        self.handle.ack_mode()
     }
 
-    pub fn read(&mut self) -> Result<(Option<DataHead>, Box<Vec<u8>>), Box<dyn Error>> {
-        self.session.write().unwrap().read(&self.name)
+    pub fn unacked(&self) -> usize {
+        self.handle.unacked()
     }
 
-    pub fn send_and_read(&mut self, data: Vec<u8>) -> Result<(Option<DataHead>, Box<Vec<u8>>), Box<dyn Error>> {
-        self.session.write().unwrap().send_and_read(data, &self.name)
+    // Lets the channel's queues fetch ahead of their consumer, on a background thread per queue,
+    // until `prefetch` messages are buffered. With manual acks a message counts against the window
+    // until it is settled, for all queues of the channel together. 0 turns prefetching off.
+    pub fn set_prefetch(&mut self, prefetch: usize) {
+        self.handle.set_prefetch(prefetch);
+    }
+
+    pub fn prefetch(&self) -> usize {
+        self.handle.prefetch()
+    }
+
+    // Puts the channel into confirm mode for good: from now on the broker acks or nacks every
+    // message pushed with Queue::push_pending or Queue::push_confirmed, see PendingConfirms.
+    pub fn enable_confirms(&mut self) {
+        self.handle.enable_confirms();
+    }
+
+    pub fn confirms_enabled(&self) -> bool {
+        self.handle.confirms_enabled()
+    }
+
+    pub fn confirms(&self) -> PendingConfirms {
+        self.handle.pending_confirms(self.session.clone())
+    }
+
+    pub fn send(&mut self, data: Vec<u8>) -> MqResult<()> {
+        self.status.check()?;
+        self.session.read().unwrap().send(data)
+    }
+
+    pub fn read(&mut self) -> MqResult<(DataHead, Payload)> {
+        self.status.check()?;
+        self.session.read().unwrap().read(&self.name)
+    }
+
+    pub fn send_and_read(&mut self, data: Vec<u8>) -> MqResult<(DataHead, Payload)> {
+        self.status.check()?;
+        self.session.read().unwrap().send_and_read(data, &self.name)
+    }
+
+    // declarations go through the session so they can be replayed after a reconnect
+    fn declare(&mut self, command_type: CommandType, name: String, routing_chain: RoutingChain) -> MqResult<()> {
+        self.status.check()?;
+        self.session.read().unwrap().declare(
+            Declaration::new(self.host_name.clone(), command_type, self.name.clone(), name, routing_chain)
+        )
     }
 }
 
 impl ChannelApi for Channel {
-    fn create_exchange(&mut self, name: String, routing_chain: RoutingChain) -> Result<(), Box<dyn Error>> {
-        let routing_mod = RoutingModFactory::new()
-            .routing_type(RoutingType::Direct)
-            .data_type(DataType::Command)
-            .command_type(CommandType::NewExchange)
-            .build();
-        self.send(
-            self.get_factory()
-                .routing_mod(routing_mod)
-                .routing_chain(routing_chain)
-                .data(name.into_bytes())
-                .build()
-        ).into()
-    }
-
-    fn create_queue(&mut self, name: String, routing_chain: RoutingChain) -> Result<(), Box<dyn Error>> {
-        let routing_mod = RoutingModFactory::new()
-            .routing_type(RoutingType::Direct)
-            .data_type(DataType::Command)
-            .command_type(CommandType::NewQueue)
-            .build();
-        self.send(
-            self.get_factory()
-                .routing_mod(routing_mod)
-                .routing_chain(routing_chain)
-                .data(name.into_bytes())
-                .build()
-        ).into()
-    }
-
-    fn drop_exchange(&mut self, name: String, routing_chain: RoutingChain) -> Result<(), Box<dyn Error>> {
-        let routing_mod = RoutingModFactory::new()
-            .routing_type(RoutingType::Direct)
-            .data_type(DataType::Command)
-            .command_type(CommandType::DropExchange)
-            .build();
-        self.send(
-            self.get_factory()
-                .routing_mod(routing_mod)
-                .routing_chain(routing_chain)
-                .data(name.into_bytes())
-                .build()
-        ).into()
-    }
-
-    fn drop_queue(&mut self, name: String, routing_chain: RoutingChain) -> Result<(), Box<dyn Error>> {
-        let routing_mod = RoutingModFactory::new()
-            .routing_type(RoutingType::Direct)
-            .data_type(DataType::Command)
-            .command_type(CommandType::DropQueue)
-            .build();
-        self.send(
-            self.get_factory()
-                .routing_mod(routing_mod)
-                .routing_chain(routing_chain)
-                .data(name.into_bytes())
-                .build()
-        ).into()
+    fn create_exchange(&mut self, name: String, routing_chain: RoutingChain) -> MqResult<()> {
+        self.declare(CommandType::NewExchange, name, routing_chain)
+    }
+
+    fn create_queue(&mut self, name: String, routing_chain: RoutingChain) -> MqResult<()> {
+        self.declare(CommandType::NewQueue, name, routing_chain)
+    }
+
+    fn create_binding(&mut self, name: String, routing_chain: RoutingChain) -> MqResult<()> {
+        self.declare(CommandType::NewBinding, name, routing_chain)
+    }
+
+    fn drop_exchange(&mut self, name: String, routing_chain: RoutingChain) -> MqResult<()> {
+        self.declare(CommandType::DropExchange, name, routing_chain)
+    }
+
+    fn drop_queue(&mut self, name: String, routing_chain: RoutingChain) -> MqResult<()> {
+        self.declare(CommandType::DropQueue, name, routing_chain)
+    }
+
+    fn drop_binding(&mut self, name: String, routing_chain: RoutingChain) -> MqResult<()> {
+        self.declare(CommandType::DropBinding, name, routing_chain)
     }
 }
 
 impl ChannelQueueApi for Channel {
-    fn get_queue(&mut self, routing_chain: RoutingChain) -> Result<Queue, Box<dyn Error>> {
-        Ok(Queue::new(routing_chain, self.name.clone(), self.host_name.clone(), self.session.clone()))
+    fn get_queue(&mut self, routing_chain: RoutingChain) -> MqResult<Queue> {
+        self.status.check()?;
+        Ok(Queue::new(routing_chain, self.handle.clone(), self.session.clone()))
     }
 }
\ No newline at end of file
@@ -0,0 +1,388 @@
+use crate::mq::io::auth::Credentials;
+use crate::mq::io::cache::{CacheLimit, OverflowPolicy};
+use crate::mq::io::events::EventBus;
+use crate::mq::io::failover::FailoverOrder;
+use crate::mq::io::reconnect::ReconnectPolicy;
+use crate::mq::io::writer::WriterConfig;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+pub const DEFAULT_PORT: u16 = 11451;
+pub const URL_SCHEME: &str = "kyuu://";
+// prefix of the environment variables read by SessionConfig::from_env
+pub const ENV_PREFIX: &str = "KYUU_";
+// reason of the ConfigError for a key `set` does not know
+const UNKNOWN_OPTION: &str = "unknown option";
+
+// Everything a session needs to know before it connects. Start from `SessionConfig::new()`,
+// a connection URL or the environment and override single values with the setters.
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    // host:port of every broker that may serve this session
+    pub endpoints: Vec<String>,
+    pub failover: FailoverOrder,
+    // None skips the login, for brokers that do not ask for one
+    pub credentials: Option<Credentials>,
+    pub virtual_host: String,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    // None sends every payload as one slice padded to 256 bytes
+    pub slice_size: Option<u32>,
+    // frames with a larger payload are refused in both directions
+    pub max_frame_size: usize,
+    pub cache_limit: CacheLimit,
+    // the broker understands the wait time of a fetch, see Queue::fetch_timeout
+    pub long_poll: bool,
+    // the broker packs several messages into one frame, see Queue::push_batch and fetch_batch
+    pub multi_message: bool,
+    // None leaves heartbeats off
+    pub heartbeat: Option<Duration>,
+    pub heartbeat_max_missed: u32,
+    pub reconnect_policy: Option<ReconnectPolicy>,
+    pub writer: WriterConfig,
+    // subscribe before opening the session to see its very first Connected event
+    pub events: EventBus,
+}
+
+impl SessionConfig {
+    pub fn new() -> SessionConfig {
+        SessionConfig {
+            endpoints: vec![format!("127.0.0.1:{DEFAULT_PORT}")],
+            failover: FailoverOrder::InOrder,
+            credentials: None,
+            virtual_host: String::from("MQ_HOST"),
+            read_timeout: Duration::from_millis(1024),
+            write_timeout: Duration::from_millis(1024),
+            slice_size: None,
+            max_frame_size: 16 * 1024 * 1024,
+            cache_limit: CacheLimit::unbounded(),
+            long_poll: false,
+            multi_message: false,
+            heartbeat: None,
+            heartbeat_max_missed: 3,
+            reconnect_policy: None,
+            writer: WriterConfig::new(),
+            events: EventBus::new(),
+        }
+    }
+
+    pub fn endpoints(mut self, endpoints: Vec<String>) -> SessionConfig {
+        self.endpoints = endpoints;
+        self
+    }
+
+    pub fn failover(mut self, failover: FailoverOrder) -> SessionConfig {
+        self.failover = failover;
+        self
+    }
+
+    pub fn credentials(mut self, credentials: Option<Credentials>) -> SessionConfig {
+        self.credentials = credentials;
+        self
+    }
+
+    pub fn virtual_host(mut self, virtual_host: String) -> SessionConfig {
+        self.virtual_host = virtual_host;
+        self
+    }
+
+    pub fn read_timeout(mut self, read_timeout: Duration) -> SessionConfig {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, write_timeout: Duration) -> SessionConfig {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    pub fn slice_size(mut self, slice_size: Option<u32>) -> Result<SessionConfig, ConfigError> {
+        if let Some(size) = slice_size {
+            // the padding of the last slice then fits into Frame's static padding buffer
+            if !size.is_power_of_two() || !(256..=4096).contains(&size) {
+                return Err(ConfigError::new("slice_size", "must be a power of two between 256 and 4096"));
+            }
+        }
+        self.slice_size = slice_size;
+        Ok(self)
+    }
+
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> SessionConfig {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    pub fn cache_limit(mut self, cache_limit: CacheLimit) -> SessionConfig {
+        self.cache_limit = cache_limit;
+        self
+    }
+
+    pub fn long_poll(mut self, long_poll: bool) -> SessionConfig {
+        self.long_poll = long_poll;
+        self
+    }
+
+    pub fn multi_message(mut self, multi_message: bool) -> SessionConfig {
+        self.multi_message = multi_message;
+        self
+    }
+
+    pub fn heartbeat(mut self, interval: Option<Duration>, max_missed: u32) -> SessionConfig {
+        self.heartbeat = interval.filter(|interval| !interval.is_zero());
+        self.heartbeat_max_missed = max_missed.max(1);
+        self
+    }
+
+    pub fn reconnect_policy(mut self, reconnect_policy: Option<ReconnectPolicy>) -> SessionConfig {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    pub fn writer(mut self, writer: WriterConfig) -> SessionConfig {
+        self.writer = writer;
+        self
+    }
+
+    pub fn events(mut self, events: EventBus) -> SessionConfig {
+        self.events = events;
+        self
+    }
+
+    // kyuu://[user[:password]@]host[:port][,host[:port]...][/vhost][?key=value&...], the keys are the ones `set` understands.
+    // User, password and values may be %-escaped.
+    pub fn from_url(url: &str) -> Result<SessionConfig, ConfigError> {
+        let rest = url.strip_prefix(URL_SCHEME)
+            .ok_or(ConfigError::new("url", &format!("must start with {URL_SCHEME}")))?;
+        // The userinfo goes first, a password may hold any of the characters split on below.
+        // An '@' after the host list has to be written as %40.
+        let (user, rest) = match rest.rsplit_once('@') {
+            Some((user, rest)) => (Some(user), rest),
+            None => (None, rest),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+        let (authority, vhost) = match rest.split_once('/') {
+            Some((authority, vhost)) => (authority, Some(vhost)),
+            None => (rest, None),
+        };
+
+        let mut config = SessionConfig::new();
+        config.endpoints = parse_endpoints(authority)?;
+        if let Some(user) = user.filter(|user| !user.is_empty()) {
+            let (username, password) = user.split_once(':').unwrap_or((user, ""));
+            config.credentials = Some(Credentials::password(percent_decode("url", username)?, percent_decode("url", password)?));
+        }
+        if let Some(vhost) = vhost.filter(|vhost| !vhost.is_empty()) {
+            config.virtual_host = vhost.to_string();
+        }
+        for pair in query.unwrap_or("").split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            config = config.set(key, &percent_decode(key, value)?)?;
+        }
+        Ok(config)
+    }
+
+    // KYUU_URL provides the base, every other KYUU_<KEY> overrides a single option, unknown keys are skipped
+    pub fn from_env() -> Result<SessionConfig, ConfigError> {
+        SessionConfig::from_vars(std::env::vars())
+    }
+
+    pub fn from_vars<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<SessionConfig, ConfigError> {
+        let mut options = vec![];
+        let mut config = SessionConfig::new();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if key == "URL" {
+                config = SessionConfig::from_url(&value)?;
+            } else {
+                options.push((key.to_lowercase(), value));
+            }
+        }
+        for (key, value) in options {
+            // other programs have KYUU_ variables too, only a bad value for a known key is an error
+            match config.clone().set(&key, &value) {
+                Ok(updated) => config = updated,
+                Err(err) if err.reason == UNKNOWN_OPTION => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn set(mut self, key: &str, value: &str) -> Result<SessionConfig, ConfigError> {
+        match key {
+            "endpoints" => self.endpoints = parse_endpoints(value)?,
+            "failover" => {
+                self.failover = match value {
+                    "in_order" => FailoverOrder::InOrder,
+                    "random" => FailoverOrder::Random,
+                    "round_robin" => FailoverOrder::RoundRobin,
+                    _ => return Err(ConfigError::new(key, "expected in_order, random or round_robin")),
+                };
+            }
+            "user" => {
+                let password = match self.credentials.take() {
+                    Some(Credentials::Password { password, .. }) => password,
+                    _ => String::new(),
+                };
+                self.credentials = Some(Credentials::password(value.to_string(), password));
+            }
+            "password" => {
+                let username = match self.credentials.take() {
+                    Some(Credentials::Password { username, .. }) => username,
+                    _ => String::new(),
+                };
+                self.credentials = Some(Credentials::password(username, value.to_string()));
+            }
+            "token" => self.credentials = Some(Credentials::token(value.to_string())),
+            "vhost" => self.virtual_host = value.to_string(),
+            "read_timeout_ms" => self.read_timeout = positive_millis(key, value)?,
+            "write_timeout_ms" => self.write_timeout = positive_millis(key, value)?,
+            "slice_size" => {
+                let size = number::<u32>(key, value)?;
+                self = self.slice_size(Some(size).filter(|size| *size != 0))?;
+            }
+            "max_frame_size" => self.max_frame_size = number(key, value)?,
+            "cache_capacity" => {
+                let capacity = number::<usize>(key, value)?;
+                self.cache_limit = CacheLimit::new(capacity, self.cache_limit.policy);
+            }
+            "cache_policy" => {
+                let policy = match value {
+                    "block" => OverflowPolicy::Block,
+                    "drop_oldest" => OverflowPolicy::DropOldest,
+                    "drop_newest" => OverflowPolicy::DropNewest,
+                    "error" => OverflowPolicy::Error,
+                    _ => return Err(ConfigError::new(key, "expected block, drop_oldest, drop_newest or error")),
+                };
+                self.cache_limit = CacheLimit::new(self.cache_limit.capacity, policy);
+            }
+            "long_poll" => self.long_poll = flag(key, value)?,
+            "multi_message" => self.multi_message = flag(key, value)?,
+            "heartbeat_ms" => {
+                let max_missed = self.heartbeat_max_missed;
+                self = self.heartbeat(Some(millis(key, value)?), max_missed);
+            }
+            "heartbeat_max_missed" => {
+                let interval = self.heartbeat;
+                self = self.heartbeat(interval, number(key, value)?);
+            }
+            "reconnect" => {
+                self.reconnect_policy = match flag(key, value)? {
+                    true => self.reconnect_policy.or_else(|| Some(ReconnectPolicy::new())),
+                    false => None,
+                };
+            }
+            "reconnect_initial_ms" => {
+                let delay = millis(key, value)?;
+                self.reconnect_policy = Some(self.reconnect_policy.unwrap_or_default().initial_delay(delay));
+            }
+            "reconnect_max_ms" => {
+                let delay = millis(key, value)?;
+                self.reconnect_policy = Some(self.reconnect_policy.unwrap_or_default().max_delay(delay));
+            }
+            "reconnect_max_attempts" => {
+                let attempts = number::<u32>(key, value)?;
+                let attempts = Some(attempts).filter(|attempts| *attempts != 0);
+                self.reconnect_policy = Some(self.reconnect_policy.unwrap_or_default().max_attempts(attempts));
+            }
+            "batch_bytes" => self.writer = self.writer.max_batch_bytes(number(key, value)?),
+            "batch_frames" => self.writer = self.writer.max_batch_frames(number(key, value)?),
+            "linger_ms" => self.writer = self.writer.linger(millis(key, value)?),
+            _ => return Err(ConfigError::new(key, UNKNOWN_OPTION)),
+        }
+        Ok(self)
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig::new()
+    }
+}
+
+fn parse_endpoints(hosts: &str) -> Result<Vec<String>, ConfigError> {
+    if hosts.split(',').any(|host| host.trim().is_empty()) {
+        return Err(ConfigError::new("endpoints", "missing host"));
+    }
+    hosts.split(',').map(|host| with_default_port(host.trim())).collect()
+}
+
+fn with_default_port(address: &str) -> Result<String, ConfigError> {
+    match address.rsplit_once(':') {
+        // a bracketed IPv6 address without a port still contains colons
+        Some((_, port)) if !port.ends_with(']') => {
+            number::<u16>("port", port)?;
+            Ok(address.to_string())
+        }
+        _ => Ok(format!("{address}:{DEFAULT_PORT}")),
+    }
+}
+
+fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.trim().parse().map_err(|_| ConfigError::new(key, &format!("{value:?} is not a valid number")))
+}
+
+fn millis(key: &str, value: &str) -> Result<Duration, ConfigError> {
+    Ok(Duration::from_millis(number(key, value)?))
+}
+
+// for timeouts, a socket cannot wait for no time at all
+fn positive_millis(key: &str, value: &str) -> Result<Duration, ConfigError> {
+    Some(millis(key, value)?).filter(|millis| !millis.is_zero()).ok_or(ConfigError::new(key, "must be more than 0"))
+}
+
+// the %XX escapes in a piece of a URL
+fn percent_decode(key: &str, value: &str) -> Result<String, ConfigError> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+        let hex = [bytes.next(), bytes.next()];
+        let escaped = match hex {
+            [Some(high), Some(low)] => std::str::from_utf8(&[high, low]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        decoded.push(escaped.ok_or(ConfigError::new(key, "broken %-escape"))?);
+    }
+    String::from_utf8(decoded).map_err(|_| ConfigError::new(key, "not valid UTF-8 once unescaped"))
+}
+
+fn flag(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value {
+        "" | "1" | "true" | "on" | "yes" => Ok(true),
+        "0" | "false" | "off" | "no" => Ok(false),
+        _ => Err(ConfigError::new(key, &format!("{value:?} is not a valid flag"))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub key: String,
+    pub reason: String,
+}
+
+impl ConfigError {
+    pub fn new(key: &str, reason: &str) -> ConfigError {
+        ConfigError {
+            key: key.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid session config {}: {}", self.key, self.reason)
+    }
+}
+
+impl Error for ConfigError {}
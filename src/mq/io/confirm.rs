@@ -0,0 +1,185 @@
+use crate::mq::error::{MqError, MqResult};
+use crate::mq::io::factory::{AckKind, Command, DataType, MessageType};
+use crate::mq::io::session::Session;
+use crate::mq::protocol::proto::DataHead;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// longest a waiter sleeps before looking for frames nobody else is reading
+const PUMP_STEP: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Confirmation {
+    // the broker stored the message
+    Acked,
+    // the broker refused the message
+    Nacked,
+    // the channel or connection went away before the broker answered, the message may or may not be stored
+    Lost,
+}
+
+#[derive(Default)]
+struct ChannelConfirms {
+    last_seq: u64,
+    outstanding: BTreeSet<u64>,
+    settled: BTreeMap<u64, Confirmation>,
+}
+
+impl ChannelConfirms {
+    fn lose_outstanding(&mut self) -> bool {
+        let lost = std::mem::take(&mut self.outstanding);
+        self.settled.extend(lost.iter().map(|seq| (*seq, Confirmation::Lost)));
+        !lost.is_empty()
+    }
+}
+
+// Publishes waiting for the broker to confirm them, per channel in confirm mode.
+// Shared by the session, which settles them as confirms arrive, and the handles of its channels.
+#[derive(Clone, Default)]
+pub struct ConfirmTracker {
+    channels: Arc<Mutex<HashMap<String, ChannelConfirms>>>,
+    settled: Arc<Condvar>,
+}
+
+impl ConfirmTracker {
+    pub fn new() -> ConfirmTracker {
+        ConfirmTracker::default()
+    }
+
+    pub fn enable(&self, channel: &str) {
+        self.channels.lock().unwrap().entry(channel.to_string()).or_default();
+    }
+
+    pub fn is_enabled(&self, channel: &str) -> bool {
+        self.channels.lock().unwrap().contains_key(channel)
+    }
+
+    // the sequence number for the next publish on the channel, None if it is not in confirm mode
+    pub fn next(&self, channel: &str) -> Option<u64> {
+        let mut channels = self.channels.lock().unwrap();
+        let confirms = channels.get_mut(channel)?;
+        confirms.last_seq += 1;
+        confirms.outstanding.insert(confirms.last_seq);
+        Some(confirms.last_seq)
+    }
+
+    // the publish was never sent, so no confirm will come for it
+    pub fn cancel(&self, channel: &str, seq: u64) {
+        if let Some(confirms) = self.channels.lock().unwrap().get_mut(channel) {
+            confirms.outstanding.remove(&seq);
+        }
+    }
+
+    pub fn settle(&self, channel: &str, seq: u64, confirmation: Confirmation) {
+        if let Some(confirms) = self.channels.lock().unwrap().get_mut(channel) {
+            if confirms.outstanding.remove(&seq) {
+                confirms.settled.insert(seq, confirmation);
+            }
+        }
+        self.settled.notify_all();
+    }
+
+    pub fn outstanding(&self, channel: &str) -> usize {
+        self.channels.lock().unwrap().get(channel).map_or(0, |confirms| confirms.outstanding.len())
+    }
+
+    // takes the confirmation of one publish once it is settled
+    pub fn take(&self, channel: &str, seq: u64) -> Option<Confirmation> {
+        self.channels.lock().unwrap().get_mut(channel)?.settled.remove(&seq)
+    }
+
+    // takes every confirmation settled so far, in publish order
+    pub fn drain(&self, channel: &str) -> Vec<(u64, Confirmation)> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.get_mut(channel).map(|confirms| std::mem::take(&mut confirms.settled).into_iter().collect()).unwrap_or_default()
+    }
+
+    // The channel closed, whatever the broker did not confirm before CLOSE-CH will not be confirmed.
+    pub fn release(&self, channel: &str) {
+        if let Some(confirms) = self.channels.lock().unwrap().get_mut(channel) {
+            confirms.lose_outstanding();
+        }
+        self.settled.notify_all();
+    }
+
+    // the connection the publishes went out on is gone, and the confirms with it
+    pub fn connection_lost(&self) {
+        for confirms in self.channels.lock().unwrap().values_mut() {
+            confirms.lose_outstanding();
+        }
+        self.settled.notify_all();
+    }
+
+    // sleeps until something is settled on any channel, or `timeout` is over
+    pub fn wait(&self, timeout: Duration) {
+        let channels = self.channels.lock().unwrap();
+        let _ = self.settled.wait_timeout(channels, timeout).unwrap();
+    }
+}
+
+// an ack frame for the publish named by its tag, as opposed to one settling a delivery
+pub fn is_confirm(head: &DataHead) -> bool {
+    Command::parse(&head.command).is_none()
+        && head.routing_mod[0] == DataType::Message as u8
+        && head.routing_mod[1] == MessageType::Ack as u8
+}
+
+pub fn confirmation(head: &DataHead) -> Confirmation {
+    match AckKind::parse(head.ack) {
+        Some(AckKind::Ack) => Confirmation::Acked,
+        _ => Confirmation::Nacked,
+    }
+}
+
+// The publishes of one channel in confirm mode. Confirms are read by whoever reads the session,
+// a waiter only reads itself when nobody else does.
+#[derive(Clone)]
+pub struct PendingConfirms {
+    channel: String,
+    tracker: ConfirmTracker,
+    session: Arc<RwLock<Session>>,
+}
+
+impl PendingConfirms {
+    pub fn new(channel: String, tracker: ConfirmTracker, session: Arc<RwLock<Session>>) -> PendingConfirms {
+        PendingConfirms {
+            channel,
+            tracker,
+            session,
+        }
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.tracker.outstanding(&self.channel)
+    }
+
+    // confirmations that arrived so far, without waiting for the rest
+    pub fn drain(&self) -> Vec<(u64, Confirmation)> {
+        self.tracker.drain(&self.channel)
+    }
+
+    pub fn wait(&self, seq: u64, timeout: Duration) -> MqResult<Confirmation> {
+        self.wait_until(Instant::now() + timeout, || self.tracker.take(&self.channel, seq))
+    }
+
+    // waits until nothing is outstanding and returns every confirmation not drained yet
+    pub fn wait_all(&self, timeout: Duration) -> MqResult<Vec<(u64, Confirmation)>> {
+        self.wait_until(Instant::now() + timeout, || (self.outstanding() == 0).then_some(()))?;
+        Ok(self.drain())
+    }
+
+    fn wait_until<T, F: FnMut() -> Option<T>>(&self, deadline: Instant, mut done: F) -> MqResult<T> {
+        loop {
+            if let Some(found) = done() {
+                return Ok(found);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(MqError::Timeout);
+            }
+            // the session lock is given up between steps, like a fetch gives it up between reads
+            self.session.read().unwrap().pump(remaining.min(PUMP_STEP))?;
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::mq::io::cache::OverflowPolicy;
+use crate::mq::io::channel::ChannelState;
+use std::fmt::{Debug, Formatter};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    Connected,
+    // `reconnecting` tells whether the session is about to try again
+    Disconnected { reason: String, reconnecting: bool },
+    Reconnected { attempts: u32 },
+    ChannelOpened { host: String, channel: String },
+    // `state` is Closed, or Failed when the connection went away underneath the channel
+    ChannelClosed { channel: String, state: ChannelState },
+    FrameDropped { channel: String, reason: DropReason },
+    // a frame arrived for a channel whose cache is full, see `policy` for what happened to it
+    CacheOverflow { channel: String, policy: OverflowPolicy },
+    UnknownChannel { channel: String },
+    // deliveries that were never settled went back to the broker, because the channel closed
+    // or the connection they were fetched on is gone
+    Requeued { channel: String, count: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropReason {
+    // the channel was closing or closed when the frame arrived
+    ChannelClosed,
+    // pushed out of a full cache by a newer frame
+    Evicted,
+    // refused by a full cache
+    CacheFull,
+}
+
+// returns false once it wants to be unsubscribed
+type Listener = Arc<dyn Fn(&SessionEvent) -> bool + Send + Sync>;
+
+// Fans session events out to listeners. Listeners run on whichever thread raised the event,
+// often one that is reading or writing for the session, so they should return quickly
+// and must not wait for a lock on the session.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    listeners: Arc<Mutex<Vec<Listener>>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    pub fn subscribe<F: Fn(&SessionEvent) + Send + Sync + 'static>(&self, listener: F) {
+        self.listeners.lock().unwrap().push(Arc::new(move |event| {
+            listener(event);
+            true
+        }));
+    }
+
+    // the receiver is unsubscribed once it is dropped
+    pub fn channel(&self) -> Receiver<SessionEvent> {
+        let (tx, rx) = channel();
+        self.listeners.lock().unwrap().push(Arc::new(move |event| tx.send(event.clone()).is_ok()));
+        rx
+    }
+
+    pub fn emit(&self, event: SessionEvent) {
+        // listeners are called without the lock held, so they may subscribe or raise events themselves
+        let listeners = self.listeners.lock().unwrap().clone();
+        let gone = listeners.into_iter()
+            .filter(|listener| !listener(&event))
+            .collect::<Vec<_>>();
+        if !gone.is_empty() {
+            self.listeners.lock().unwrap().retain(|listener| !gone.iter().any(|g| Arc::ptr_eq(g, listener)));
+        }
+    }
+}
+
+impl Debug for EventBus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBus").field("listeners", &self.listeners.lock().unwrap().len()).finish()
+    }
+}
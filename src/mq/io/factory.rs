@@ -1,10 +1,26 @@
 use std::cmp::min;
+use std::time::Duration;
+use crate::mq::io::frame::Frame;
 use crate::mq::protocol::proto::DataHead;
 use crate::mq::protocol::protobase::Serialize;
 use crate::mq::routing::chain::RoutingChain;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Command {
     CloseChannel,
+    Heartbeat,
+    Auth,
+}
+
+impl Command {
+    pub fn parse(command: &[u8; 24]) -> Option<Command> {
+        match String::from_utf8_lossy(command).trim_end_matches('\0') {
+            "CLOSE-CH" => Some(Command::CloseChannel),
+            "HEARTBEAT" => Some(Command::Heartbeat),
+            "AUTH" => Some(Command::Auth),
+            _ => None
+        }
+    }
 }
 
 #[repr(u8)]
@@ -15,7 +31,7 @@ pub enum DataType {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CommandType {
     NewQueue = 0u8,
     NewExchange = 1u8,
@@ -25,6 +41,9 @@ pub enum CommandType {
     DropExchange = 4u8,
     DropBinding = 5u8,
 
+    NewVirtualHost = 6u8,
+    DropVirtualHost = 7u8,
+
     Nop = 0xfu8
 }
 
@@ -33,11 +52,40 @@ pub enum CommandType {
 pub enum MessageType {
     Push = 0u8,
     Fetch = 1u8,
+    // settles a delivery, see AckKind
+    Ack = 2u8,
     Nop = 0xfu8
 }
 
+// The ack field of a frame. On a fetch, Ack asks the broker to keep the message until it is
+// settled; on an ack frame it says how the delivery named by the frame's tag was settled.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AckKind {
+    None = 0u16,
+    Ack = 1u16,
+    Requeue = 2u16,
+    // dropped without being redelivered
+    Drop = 3u16,
+    // dropped because the consumer cannot handle the message at all
+    Reject = 4u16
+}
+
+impl AckKind {
+    pub fn parse(ack: u16) -> Option<AckKind> {
+        match ack {
+            0 => Some(AckKind::None),
+            1 => Some(AckKind::Ack),
+            2 => Some(AckKind::Requeue),
+            3 => Some(AckKind::Drop),
+            4 => Some(AckKind::Reject),
+            _ => None
+        }
+    }
+}
+
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RoutingType {
     Direct = 0u8,
     Topic = 1u8,
@@ -45,6 +93,17 @@ pub enum RoutingType {
     Nop = 0xfu8
 }
 
+impl RoutingType {
+    pub fn parse(routing_type: u8) -> RoutingType {
+        match routing_type {
+            0 => RoutingType::Direct,
+            1 => RoutingType::Topic,
+            2 => RoutingType::Fanout,
+            _ => RoutingType::Nop
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RoutingMod {
     pub data_type: DataType,
@@ -53,7 +112,7 @@ pub struct RoutingMod {
     pub routing_type: RoutingType
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Routing {
     Route(String),
     Any,
@@ -68,7 +127,12 @@ pub struct MessageFactory {
     command: Option<Command>,
     route: Vec<Routing>,
     queue_name: String,
-    data: Vec<u8>
+    data: Vec<u8>,
+    slice_size: Option<u32>,
+    wait: Option<Duration>,
+    count: u32,
+    ack: AckKind,
+    delivery_tag: u64
 }
 
 pub struct RoutingModFactory {
@@ -135,7 +199,12 @@ impl MessageFactory {
             command: None,
             route: vec![],
             queue_name: String::from(""),
-            data: vec![]
+            data: vec![],
+            slice_size: None,
+            wait: None,
+            count: 0,
+            ack: AckKind::None,
+            delivery_tag: 0
         }
     }
 
@@ -167,17 +236,47 @@ impl MessageFactory {
         self
     }
 
-    pub fn data(mut self, mut data: Vec<u8>) -> MessageFactory {
-        if data.len() % 256 != 0 {
-            let delta = 256 - data.len() % 256;
-            data.resize(data.len() + delta, 0u8);
-        }
+    // the payload is padded to a multiple of the slice size (256 bytes by default) on the wire, see build_frame
+    pub fn data(mut self, data: Vec<u8>) -> MessageFactory {
         self.data = data;
         self
     }
 
-    pub fn build(mut self) -> Vec<u8> {
-        let mut serialized = vec![];
+    // Splits the payload into slices of `slice_size` bytes instead of sending one padded slice.
+    // Some(0) is taken as None, the way SessionConfig::set takes slice_size=0.
+    pub fn slice_size(mut self, slice_size: Option<u32>) -> MessageFactory {
+        self.slice_size = slice_size.filter(|size| *size != 0);
+        self
+    }
+
+    // long polling: the broker answers a fetch for an empty queue once an item arrives or `wait` is over
+    pub fn wait(mut self, wait: Option<Duration>) -> MessageFactory {
+        self.wait = wait;
+        self
+    }
+
+    // number of messages packed into the payload, 0 for a payload that is a single message.
+    // On a fetch it is the most messages the caller wants back in one frame.
+    pub fn count(mut self, count: u32) -> MessageFactory {
+        self.count = count;
+        self
+    }
+
+    pub fn ack(mut self, ack: AckKind) -> MessageFactory {
+        self.ack = ack;
+        self
+    }
+
+    pub fn delivery_tag(mut self, delivery_tag: u64) -> MessageFactory {
+        self.delivery_tag = delivery_tag;
+        self
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.build_frame().into_vec()
+    }
+
+    pub fn build_frame(self) -> Frame {
         let mut channel_serialized = self.channel.as_bytes().to_vec();
         channel_serialized.resize(32, 0u8);
 
@@ -193,6 +292,9 @@ impl MessageFactory {
                     MessageType::Fetch => {
                         routing_mod[1] = 1u8;
                     }
+                    MessageType::Ack => {
+                        routing_mod[1] = 2u8;
+                    }
                     MessageType::Nop => {
                         routing_mod[1] = 0xfu8;
                     }
@@ -219,6 +321,12 @@ impl MessageFactory {
                     CommandType::DropBinding => {
                         routing_mod[1] = 5u8;
                     }
+                    CommandType::NewVirtualHost => {
+                        routing_mod[1] = 6u8;
+                    }
+                    CommandType::DropVirtualHost => {
+                        routing_mod[1] = 7u8;
+                    }
                     CommandType::Nop => {
                         routing_mod[1] = 0xfu8;
                     }
@@ -248,6 +356,16 @@ impl MessageFactory {
                         vec.resize(24, 0u8);
                         <[u8; 24]>::try_from(vec).unwrap()
                     }
+                    Command::Heartbeat => {
+                        let mut vec = String::from("HEARTBEAT").as_bytes().to_vec();
+                        vec.resize(24, 0u8);
+                        <[u8; 24]>::try_from(vec).unwrap()
+                    }
+                    Command::Auth => {
+                        let mut vec = String::from("AUTH").as_bytes().to_vec();
+                        vec.resize(24, 0u8);
+                        <[u8; 24]>::try_from(vec).unwrap()
+                    }
                 }
             } else {
                 [0u8; 24]
@@ -292,20 +410,31 @@ impl MessageFactory {
             <[u8; 32]>::try_from(queue_tmp).unwrap()
         ].concat()).unwrap();
 
-        let head = DataHead::new(
+        let unit = self.slice_size.unwrap_or(256) as usize;
+        let padding = (unit - self.data.len() % unit) % unit;
+        let (slice_count, slice_size) = match self.slice_size {
+            Some(size) => (((self.data.len() + padding) / unit) as u32, size),
+            None => (1, (self.data.len() + padding) as u32),
+        };
+        let mut head = DataHead::new(
             self.host,
             <[u8; 32]>::try_from(channel_serialized).unwrap(),
             routing_mod,
             command_serialized,
             route_serialized,
-            1,
-            self.data.len() as u32,
-            0u32,
+            slice_count,
+            slice_size,
+            self.count,
             0u16
         );
+        head.set_body_len(self.data.len());
+        head.ack = self.ack as u16;
+        head.set_delivery_tag(self.delivery_tag);
+        if let Some(wait) = self.wait {
+            // rounded up, a broker answering a little early would only be asked again
+            head.set_wait_ms(wait.as_micros().div_ceil(1000).clamp(1, u32::MAX as u128) as u32);
+        }
 
-        serialized.append(&mut head.serialize_vec());
-        serialized.append(&mut self.data);
-        serialized
+        Frame::new(head.serialize_vec(), self.data, padding)
     }
 }
\ No newline at end of file
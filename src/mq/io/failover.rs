@@ -0,0 +1,125 @@
+use crate::mq::io::reconnect::random_unit;
+use crate::mq::io::transport::{Connector, TcpTransport, Transport};
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::Mutex;
+
+// In which order the endpoints are tried whenever the session (re)connects.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailoverOrder {
+    // always prefer the first endpoint that is up
+    InOrder,
+    Random,
+    // start after the endpoint that was used last
+    RoundRobin,
+}
+
+struct FailoverState {
+    // the endpoint of the current connection
+    current: Option<usize>,
+    // set when the connection to `current` broke, that endpoint is then tried last
+    failed: Option<usize>,
+    connects: u32,
+}
+
+// Spreads connects over several brokers: a single connect tries every endpoint once before it fails.
+pub struct FailoverConnector {
+    endpoints: Vec<Box<dyn Connector>>,
+    order: FailoverOrder,
+    state: Mutex<FailoverState>,
+}
+
+impl FailoverConnector {
+    pub fn new(endpoints: Vec<Box<dyn Connector>>, order: FailoverOrder) -> FailoverConnector {
+        FailoverConnector {
+            endpoints,
+            order,
+            state: Mutex::new(FailoverState {
+                current: None,
+                failed: None,
+                connects: 0,
+            }),
+        }
+    }
+
+    // host names are resolved on every connect, so a broker may move to another address
+    pub fn tcp(endpoints: Vec<String>, order: FailoverOrder) -> FailoverConnector {
+        FailoverConnector::new(
+            endpoints.into_iter()
+                .map(|address| Box::new(TcpHostConnector::new(address)) as Box<dyn Connector>)
+                .collect(),
+            order
+        )
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.state.lock().unwrap().current
+    }
+
+    fn attempt_order(&self, state: &FailoverState) -> Vec<usize> {
+        let len = self.endpoints.len();
+        let mut order = (0..len).collect::<Vec<_>>();
+        match self.order {
+            FailoverOrder::InOrder => {}
+            FailoverOrder::Random => {
+                for i in (1..len).rev() {
+                    let j = (random_unit(state.connects ^ i as u32) * (i + 1) as f64) as usize;
+                    order.swap(i, j.min(i));
+                }
+            }
+            FailoverOrder::RoundRobin => {
+                let start = state.current.map(|current| current + 1).unwrap_or(0);
+                order.rotate_left(start % len.max(1));
+            }
+        }
+        if let Some(failed) = state.failed {
+            order.retain(|i| *i != failed);
+            order.push(failed);
+        }
+        order
+    }
+}
+
+impl Connector for FailoverConnector {
+    fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        let mut state = self.state.lock().unwrap();
+        let order = self.attempt_order(&state);
+        state.connects = state.connects.wrapping_add(1);
+        let mut errors = vec![];
+        for i in order {
+            match self.endpoints[i].connect() {
+                Ok(transport) => {
+                    state.current = Some(i);
+                    state.failed = None;
+                    return Ok(transport);
+                }
+                Err(err) => errors.push(format!("endpoint {i}: {err}")),
+            }
+        }
+        if errors.is_empty() {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "no broker endpoints configured"));
+        }
+        Err(std::io::Error::new(ErrorKind::ConnectionRefused, format!("all endpoints failed ({})", errors.join(", "))))
+    }
+
+    fn mark_failed(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failed = state.current;
+    }
+}
+
+pub struct TcpHostConnector {
+    address: String,
+}
+
+impl TcpHostConnector {
+    pub fn new(address: String) -> TcpHostConnector {
+        TcpHostConnector { address }
+    }
+}
+
+impl Connector for TcpHostConnector {
+    fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        Ok(Box::new(TcpTransport::new(TcpStream::connect(self.address.as_str())?)))
+    }
+}
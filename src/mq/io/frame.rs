@@ -0,0 +1,58 @@
+use std::io::IoSlice;
+
+// large enough for the slice sizes SessionConfig::slice_size accepts, more padding goes on the heap
+static PADDING: [u8; 4096] = [0u8; 4096];
+
+// A serialized message kept in pieces, so the header and the payload can go out
+// in one vectored write without being copied into a single buffer first.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub head: Vec<u8>,
+    pub payload: Vec<u8>,
+    // zero bytes appended after the payload to fill up the last slice
+    pub padding: usize,
+}
+
+impl Frame {
+    pub fn new(head: Vec<u8>, mut payload: Vec<u8>, padding: usize) -> Frame {
+        if padding > PADDING.len() {
+            // the head announces the padded length, a frame may never come up short
+            payload.resize(payload.len() + padding, 0);
+            return Frame { head, payload, padding: 0 };
+        }
+        Frame {
+            head,
+            payload,
+            padding,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.head.len() + self.payload.len() + self.padding
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn io_slices(&self) -> [IoSlice<'_>; 3] {
+        [
+            IoSlice::new(&self.head),
+            IoSlice::new(&self.payload),
+            IoSlice::new(&PADDING[..self.padding]),
+        ]
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        let mut serialized = self.head;
+        serialized.extend_from_slice(&self.payload);
+        serialized.extend_from_slice(&PADDING[..self.padding]);
+        serialized
+    }
+}
+
+impl From<Vec<u8>> for Frame {
+    fn from(data: Vec<u8>) -> Self {
+        Frame::new(data, vec![], 0)
+    }
+}
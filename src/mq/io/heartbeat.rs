@@ -0,0 +1,124 @@
+use crate::mq::io::factory::{Command, MessageFactory};
+use crate::mq::io::session::Session;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::{RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub max_missed: u32,
+    pub last_sent: Instant,
+    pub last_received: Instant,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, max_missed: u32) -> Heartbeat {
+        Heartbeat {
+            interval,
+            max_missed: max_missed.max(1),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+        }
+    }
+
+    pub fn frame(host: String, interval: Duration) -> Vec<u8> {
+        // the proposed interval travels in the payload as little-endian milliseconds
+        let millis = interval.as_millis().min(u32::MAX as u128) as u32;
+        MessageFactory::new(host, String::new())
+            .command(Command::Heartbeat)
+            .data(millis.to_le_bytes().to_vec())
+            .build()
+    }
+
+    pub fn parse_interval(data: &[u8]) -> Option<Duration> {
+        let millis = u32::from_le_bytes(<[u8; 4]>::try_from(data.get(0..4)?).ok()?);
+        if millis == 0 {
+            None
+        } else {
+            Some(Duration::from_millis(millis as u64))
+        }
+    }
+
+    pub fn negotiate(proposed: Duration, offered: Option<Duration>) -> Duration {
+        match offered {
+            Some(offered) => proposed.min(offered),
+            None => proposed,
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last_sent.elapsed() >= self.interval
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.last_received.elapsed() > self.interval * self.max_missed
+    }
+
+    pub fn missed(&self) -> u32 {
+        (self.last_received.elapsed().as_millis() / self.interval.as_millis().max(1)) as u32
+    }
+
+    // Drives the heartbeat of a session until the session is gone, heartbeats are disabled or
+    // a thread of a later `generation` took over. It wakes up every STEP so an interval
+    // negotiated again while it runs takes effect at once.
+    pub fn spawn(session: Weak<RwLock<Session>>, generation: u64) -> thread::JoinHandle<()> {
+        const STEP: Duration = Duration::from_millis(10);
+        thread::spawn(move || {
+            let mut last_tick = Instant::now();
+            loop {
+                thread::sleep(STEP);
+                let Some(session) = session.upgrade() else {
+                    break;
+                };
+                let session = session.read().unwrap();
+                if session.heartbeat_thread() != generation {
+                    break;
+                }
+                let Some(interval) = session.heartbeat_interval() else {
+                    break;
+                };
+                if last_tick.elapsed() < (interval / 4).max(STEP) {
+                    continue;
+                }
+                last_tick = Instant::now();
+                if !session.heartbeat_tick() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Disconnected {
+    pub reason: String,
+}
+
+impl Disconnected {
+    pub fn new(reason: String) -> Disconnected {
+        Disconnected { reason }
+    }
+
+    pub fn io(reason: String) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::NotConnected, Disconnected::new(reason))
+    }
+
+    pub fn is(err: &(dyn Error + 'static)) -> bool {
+        if err.is::<Disconnected>() {
+            return true;
+        }
+        err.downcast_ref::<std::io::Error>()
+            .and_then(|err| err.get_ref())
+            .is_some_and(|inner| inner.is::<Disconnected>())
+    }
+}
+
+impl Display for Disconnected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "disconnected: {}", self.reason)
+    }
+}
+
+impl Error for Disconnected {}
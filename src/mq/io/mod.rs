@@ -1,3 +1,17 @@
 pub mod session;
 pub mod channel;
-pub mod factory;
\ No newline at end of file
+pub mod factory;
+pub mod reconnect;
+pub mod topology;
+pub mod heartbeat;
+pub mod transport;
+pub mod writer;
+pub mod frame;
+pub mod pool;
+pub mod cache;
+pub mod config;
+pub mod failover;
+pub mod auth;
+pub mod events;
+pub mod ack;
+pub mod confirm;
\ No newline at end of file
@@ -0,0 +1,159 @@
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, Range};
+use std::sync::{Arc, Mutex, Weak};
+
+struct PoolInner {
+    free: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    // larger buffers are released instead of being kept around
+    max_capacity: usize,
+}
+
+// Recycles read buffers: a buffer goes back to the pool once the last Payload pointing into it is dropped.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize, max_capacity: usize) -> BufferPool {
+        BufferPool {
+            inner: Arc::new(PoolInner {
+                free: Mutex::new(Vec::with_capacity(max_buffers)),
+                max_buffers,
+                max_capacity,
+            }),
+        }
+    }
+
+    // a zeroed buffer of exactly `len` bytes, reused from the pool when one is big enough,
+    // the smallest that fits so large buffers stay around for large frames
+    pub fn take(&self, len: usize) -> Vec<u8> {
+        if len == 0 {
+            return Vec::new();
+        }
+        let reused = {
+            let mut free = self.inner.free.lock().unwrap();
+            free.iter()
+                .enumerate()
+                .filter(|(_, buf)| buf.capacity() >= len)
+                .min_by_key(|(_, buf)| buf.capacity())
+                .map(|(i, _)| i)
+                .map(|i| free.swap_remove(i))
+        };
+        let mut buf = reused.unwrap_or_else(|| Vec::with_capacity(len));
+        buf.clear();
+        buf.resize(len, 0u8);
+        buf
+    }
+
+    pub fn payload(&self, buf: Vec<u8>) -> Payload {
+        let end = buf.len();
+        Payload {
+            buf: Arc::new(PooledBuffer {
+                data: buf,
+                pool: Some(Arc::downgrade(&self.inner)),
+            }),
+            start: 0,
+            end,
+        }
+    }
+
+    pub fn pooled(&self) -> usize {
+        self.inner.free.lock().unwrap().len()
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        BufferPool::new(64, 1024 * 1024)
+    }
+}
+
+struct PooledBuffer {
+    data: Vec<u8>,
+    pool: Option<Weak<PoolInner>>,
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let Some(pool) = self.pool.take().and_then(|pool| pool.upgrade()) else {
+            return;
+        };
+        if self.data.capacity() > pool.max_capacity {
+            return;
+        }
+        let mut free = pool.free.lock().unwrap();
+        if free.len() < pool.max_buffers {
+            free.push(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+// A read-only view into a received frame. Cloning and slicing only bump a reference count.
+#[derive(Clone)]
+pub struct Payload {
+    buf: Arc<PooledBuffer>,
+    start: usize,
+    end: usize,
+}
+
+impl Payload {
+    pub fn from_vec(data: Vec<u8>) -> Payload {
+        let end = data.len();
+        Payload {
+            buf: Arc::new(PooledBuffer { data, pool: None }),
+            start: 0,
+            end,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf.data[self.start..self.end]
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Payload {
+        assert!(range.start <= range.end && range.end <= self.len(), "payload slice out of range");
+        Payload {
+            buf: self.buf.clone(),
+            start: self.start + range.start,
+            end: self.start + range.end,
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.as_slice().to_vec()
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for Payload {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl PartialEq for Payload {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Debug for Payload {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Payload").field("len", &self.len()).finish()
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(data: Vec<u8>) -> Self {
+        Payload::from_vec(data)
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    // fraction of the computed delay that is randomized, 0.0 disables jitter
+    pub jitter: f64,
+    // None retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+
+    pub fn initial_delay(mut self, initial_delay: Duration) -> ReconnectPolicy {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    pub fn max_delay(mut self, max_delay: Duration) -> ReconnectPolicy {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> ReconnectPolicy {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    pub fn jitter(mut self, jitter: f64) -> ReconnectPolicy {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: Option<u32>) -> ReconnectPolicy {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        match self.max_attempts {
+            Some(max) => attempt < max,
+            None => true,
+        }
+    }
+
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.initial_delay.as_secs_f64() * self.multiplier.powi(attempt.min(64) as i32);
+        let base = base.min(self.max_delay.as_secs_f64());
+        // spread the delay over [base * (1 - jitter), base * (1 + jitter)]
+        let spread = base * self.jitter * (2.0 * random_unit(attempt) - 1.0);
+        Duration::from_secs_f64((base + spread).clamp(0.0, self.max_delay.as_secs_f64()))
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy::new()
+    }
+}
+
+// xorshift over the clock, good enough to de-synchronize clients without pulling in a rng crate
+pub(crate) fn random_unit(salt: u32) -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64 ^ d.as_secs())
+        .unwrap_or(0);
+    let mut x = nanos ^ ((salt as u64) << 32) ^ 0x9e37_79b9_7f4a_7c15;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -1,127 +1,857 @@
-use crate::mq::io::channel::Channel;
+use crate::mq::error::{MqError, MqResult};
+use crate::mq::io::ack::UnackedTracker;
+use crate::mq::io::auth::AuthError;
+use crate::mq::io::cache::{CacheLimit, CacheStats, CachedFrame, ChannelCache, OverflowPolicy};
+use crate::mq::io::channel::{Channel, ChannelHandle, ChannelState, ChannelStatus};
+use crate::mq::io::config::SessionConfig;
+use crate::mq::io::confirm::{self, ConfirmTracker};
+use crate::mq::io::events::{DropReason, EventBus, SessionEvent};
+use crate::mq::io::failover::FailoverConnector;
+use crate::mq::io::factory::{Command, CommandType, MessageFactory};
+use crate::mq::io::heartbeat::{Disconnected, Heartbeat};
+use crate::mq::io::reconnect::ReconnectPolicy;
+use crate::mq::io::topology::{Declaration, Topology};
+use crate::mq::io::transport::{Connector, TcpConnector, Transport};
+use crate::mq::io::frame::Frame;
+use crate::mq::io::pool::{BufferPool, Payload};
+use crate::mq::io::writer::{Writer, WriterConfig};
 use crate::mq::protocol::proto::DataHead;
 use crate::mq::protocol::protobase::Deserialize;
-use std::collections::{HashMap, VecDeque};
-use std::error::Error;
-use std::io::{Read, Write};
-use std::net::{TcpStream, ToSocketAddrs};
-use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read};
+use std::net::ToSocketAddrs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
 
+// Every method that moves data takes &self: callers only need a shared lock on the
+// session, readers serialize on `reader` and producers hand their frames to `writer`.
 pub struct Session {
-    stream: Arc<RwLock<TcpStream>>,
+    reader: Mutex<Box<dyn Transport>>,
+    writer: Writer,
+    // a third handle on the connection for timeouts and shutdown, so neither half has to be waited for
+    control: Mutex<Box<dyn Transport>>,
+    connector: Box<dyn Connector>,
     host: String,
-    channels: HashMap<String, Arc<RwLock<Channel>>>,
-    self_ref: Option<Arc<RwLock<Session>>>,
-    cache: HashMap<String, VecDeque<Box<Vec<u8>>>>
+    // only the status, so dropping the last handle on a channel closes it
+    channels: HashMap<String, ChannelStatus>,
+    // weak, the session has to be droppable once its last outside handle is gone
+    self_ref: Option<Weak<RwLock<Session>>>,
+    cache: Mutex<HashMap<String, ChannelCache>>,
+    // signalled whenever a consumer takes a frame out of its cache
+    cache_space: Condvar,
+    pool: BufferPool,
+    config: SessionConfig,
+    reconnect_lock: Mutex<()>,
+    // bumped on every successful reconnect, lets concurrent failures reconnect only once
+    generation: AtomicU64,
+    topology: Mutex<Topology>,
+    heartbeat: Mutex<Option<Heartbeat>>,
+    // bumped for every heartbeat thread started, an older thread sees it was replaced and stops
+    heartbeat_thread: AtomicU64,
+    // what the broker offered in its answer to our last heartbeat, whoever happened to read it
+    heartbeat_reply: Mutex<Option<Option<Duration>>>,
+    heartbeat_ready: Condvar,
+    disconnected: Mutex<Option<String>>,
+    // errcode of the broker's answer to our last AUTH frame, whoever happened to read it
+    auth_reply: Mutex<Option<u16>>,
+    auth_ready: Condvar,
+    unacked: UnackedTracker,
+    confirms: ConfirmTracker,
 }
 
 impl Session {
-    pub fn new<A: ToSocketAddrs>(addr: A, host: String) -> Session {
-        Session {
-            stream: Arc::new(RwLock::new(TcpStream::connect(addr).unwrap())),
-            host,
+    pub fn new<A: ToSocketAddrs>(addr: A, host: String) -> Result<Session, std::io::Error> {
+        Session::with_connector(Box::new(TcpConnector::new(addr)?), host)
+    }
+
+    // connects over TCP, initializes the session and starts heartbeats if the config asks for them
+    pub fn open(config: SessionConfig) -> MqResult<Arc<RwLock<Session>>> {
+        let connector = FailoverConnector::tcp(config.endpoints.clone(), config.failover);
+        Session::open_with(Box::new(connector), config)
+    }
+
+    pub fn open_url(url: &str) -> MqResult<Arc<RwLock<Session>>> {
+        Session::open(SessionConfig::from_url(url)?)
+    }
+
+    pub fn open_with(connector: Box<dyn Connector>, config: SessionConfig) -> MqResult<Arc<RwLock<Session>>> {
+        let heartbeat = config.heartbeat.map(|interval| (interval, config.heartbeat_max_missed));
+        let session = Arc::new(RwLock::new(Session::with_config(connector, config)?));
+        session.write().unwrap().init(session.clone())?;
+        session.read().unwrap().authenticate()?;
+        if let Some((interval, max_missed)) = heartbeat {
+            session.read().unwrap().enable_heartbeat(interval, max_missed)?;
+        }
+        Ok(session)
+    }
+
+    pub fn with_connector(connector: Box<dyn Connector>, host: String) -> Result<Session, std::io::Error> {
+        Session::with_config(connector, SessionConfig::new().virtual_host(host))
+    }
+
+    // only half a session: logging in and heartbeats need it behind its Arc, see Session::open_with
+    fn with_config(connector: Box<dyn Connector>, config: SessionConfig) -> Result<Session, std::io::Error> {
+        let reader = connector.connect()?;
+        let writer = Writer::spawn(reader.try_clone()?, config.writer.clone());
+        let control = reader.try_clone()?;
+        config.events.emit(SessionEvent::Connected);
+        Ok(Session {
+            reader: Mutex::new(reader),
+            writer,
+            control: Mutex::new(control),
+            connector,
+            host: config.virtual_host.clone(),
             channels: HashMap::new(),
             self_ref: None,
-            cache: HashMap::new(),
-        }
+            cache: Mutex::new(HashMap::new()),
+            cache_space: Condvar::new(),
+            pool: BufferPool::default(),
+            config,
+            reconnect_lock: Mutex::new(()),
+            generation: AtomicU64::new(0),
+            topology: Mutex::new(Topology::new()),
+            heartbeat: Mutex::new(None),
+            heartbeat_thread: AtomicU64::new(0),
+            heartbeat_reply: Mutex::new(None),
+            heartbeat_ready: Condvar::new(),
+            disconnected: Mutex::new(None),
+            auth_reply: Mutex::new(None),
+            auth_ready: Condvar::new(),
+            unacked: UnackedTracker::new(),
+            confirms: ConfirmTracker::new(),
+        })
     }
 
-    pub fn init(&mut self, self_ref: Arc<RwLock<Session>>) -> Arc<RwLock<Session>> {
-        self.self_ref = Some(self_ref.clone());
-        self.set_write_timeout(std::time::Duration::from_millis(1024));
-        self.set_read_timeout(std::time::Duration::from_millis(1024));
-        self_ref
+    pub fn init(&mut self, self_ref: Arc<RwLock<Session>>) -> Result<Arc<RwLock<Session>>, std::io::Error> {
+        self.self_ref = Some(Arc::downgrade(&self_ref));
+        self.set_write_timeout(self.config.write_timeout)?;
+        self.set_read_timeout(self.config.read_timeout)?;
+        Ok(self_ref)
     }
 
-    pub fn set_read_timeout(&self, timeout: std::time::Duration) {
+    pub fn set_read_timeout(&mut self, timeout: Duration) -> Result<(), std::io::Error> {
         // must set a read timeout, otherwise the stream will block forever!!
-        self.stream.write().unwrap().set_read_timeout(Some(timeout)).unwrap();
+        self.control.lock().unwrap().set_read_timeout(Some(positive(timeout, "read")?))?;
+        self.config.read_timeout = timeout;
+        Ok(())
+    }
+
+    pub fn set_write_timeout(&mut self, timeout: Duration) -> Result<(), std::io::Error> {
+        self.control.lock().unwrap().set_write_timeout(Some(positive(timeout, "write")?))?;
+        self.config.write_timeout = timeout;
+        Ok(())
+    }
+
+    pub fn set_writer_config(&mut self, config: WriterConfig) -> Result<(), std::io::Error> {
+        self.config.writer = config.clone();
+        self.writer.configure(config)
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.config.events
+    }
+
+    // a message factory that follows the session's framing settings
+    pub fn factory(&self, host: String, channel: String) -> MessageFactory {
+        MessageFactory::new(host, channel).slice_size(self.config.slice_size)
+    }
+
+    // replaces the pool read buffers are taken from, buffers still in use return to the old one
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.pool = pool;
+    }
+
+    // applies to channels created from now on
+    pub fn set_cache_limit(&mut self, limit: CacheLimit) {
+        self.config.cache_limit = limit;
+    }
+
+    pub fn set_channel_cache_limit(&self, channel: &String, limit: CacheLimit) {
+        if let Some(cache) = self.cache.lock().unwrap().get_mut(channel) {
+            cache.set_limit(limit);
+        }
+        self.cache_space.notify_all();
+    }
+
+    pub fn cache_stats(&self, channel: &String) -> Option<CacheStats> {
+        self.cache.lock().unwrap().get(channel).map(|cache| cache.stats())
+    }
+
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.config.reconnect_policy = policy;
+    }
+
+    pub fn topology(&self) -> Vec<Declaration> {
+        self.topology.lock().unwrap().declarations().clone()
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.lock().unwrap().is_some()
+    }
+
+    // proposes `interval` to the broker and settles on the shorter of the two,
+    // the connection is declared dead after `max_missed` intervals without any frame from the broker
+    pub fn enable_heartbeat(&self, interval: Duration, max_missed: u32) -> MqResult<Duration> {
+        if interval.is_zero() {
+            return Err(MqError::Validation(String::from("heartbeat interval must not be zero")));
+        }
+        let self_ref = self.self_ref.clone().ok_or(MqError::Validation(String::from("session is not initialized")))?;
+        self.ensure_alive()?;
+        *self.heartbeat_reply.lock().unwrap() = None;
+        self.writer.write(Heartbeat::frame(self.host.clone(), interval).into()).map_err(|(err, _)| err)?;
+
+        let deadline = Instant::now() + self.config.read_timeout;
+        let offered = self.await_reply(&self.heartbeat_reply, &self.heartbeat_ready, deadline)?.ok_or(MqError::Timeout)?;
+
+        let negotiated = Heartbeat::negotiate(interval, offered);
+        let mut heartbeat = self.heartbeat.lock().unwrap();
+        if heartbeat.replace(Heartbeat::new(negotiated, max_missed)).is_none() {
+            // a thread left over from before a disable may still be around, it stops at its next step
+            let generation = self.heartbeat_thread.fetch_add(1, Ordering::SeqCst) + 1;
+            Heartbeat::spawn(self_ref, generation);
+        }
+        Ok(negotiated)
+    }
+
+    // the negotiated interval, None while heartbeats are off
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat.lock().unwrap().as_ref().map(|hb| hb.interval)
+    }
+
+    // the generation of the heartbeat thread that is meant to be running
+    pub fn heartbeat_thread(&self) -> u64 {
+        self.heartbeat_thread.load(Ordering::SeqCst)
+    }
+
+    // logs in with the configured credentials, a session without credentials skips the login
+    fn authenticate(&self) -> Result<(), std::io::Error> {
+        let Some(credentials) = self.config.credentials.as_ref() else {
+            return Ok(());
+        };
+        *self.auth_reply.lock().unwrap() = None;
+        self.writer.write(credentials.frame(self.host.clone()).into()).map_err(|(err, _)| err)?;
+
+        let deadline = Instant::now() + self.config.read_timeout;
+        match self.await_reply(&self.auth_reply, &self.auth_ready, deadline)? {
+            Some(errcode) => match AuthError::from_errcode(errcode) {
+                Some(err) => Err(err.io()),
+                None => Ok(()),
+            },
+            None => Err(AuthError::NoResponse.io()),
+        }
     }
 
-    pub fn set_write_timeout(&self, timeout: std::time::Duration) {
-        self.stream.write().unwrap().set_write_timeout(Some(timeout)).unwrap();
+    // Reads until dispatch fills `slot`, None if nothing came before `deadline`. While
+    // somebody else is reading it waits for them to hand the reply over instead.
+    fn await_reply<T>(&self, slot: &Mutex<Option<T>>, ready: &Condvar, deadline: Instant) -> Result<Option<T>, std::io::Error> {
+        loop {
+            if let Some(reply) = slot.lock().unwrap().take() {
+                return Ok(Some(reply));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            let frame = match self.reader.try_lock() {
+                Ok(mut reader) => self.read_frame(&mut reader),
+                Err(_) => {
+                    let reply = slot.lock().unwrap();
+                    if reply.is_none() {
+                        let _ = ready.wait_timeout(reply, remaining.min(Duration::from_millis(50))).unwrap();
+                    }
+                    continue;
+                }
+            };
+            match frame {
+                Ok((head, buf)) => self.dispatch(head, buf).map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err.to_string()))?,
+                Err(err) if is_timeout(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub fn disable_heartbeat(&self) {
+        *self.heartbeat.lock().unwrap() = None;
     }
 
-    pub fn create_channel(&mut self, name: String) -> Option<Arc<RwLock<Channel>>> {
-        if self.channels.contains_key(&name) {
+    // called periodically by the heartbeat thread, returns false once the thread should stop
+    pub fn heartbeat_tick(&self) -> bool {
+        if self.heartbeat.lock().unwrap().is_none() || self.is_disconnected() {
+            return false;
+        }
+        let generation = self.generation.load(Ordering::SeqCst);
+        // drain what has already arrived so heartbeat replies are seen even if nobody is reading,
+        // but never wait for a reader that is already blocked on the stream
+        loop {
+            let frame = {
+                let Ok(mut reader) = self.reader.try_lock() else {
+                    break;
+                };
+                if !reader.poll_ready(256).unwrap_or(true) {
+                    break;
+                }
+                self.read_frame(&mut reader)
+            };
+            // dispatch outside the reader lock, it may have to wait for room in a full cache
+            match frame {
+                Ok((head, buf)) => {
+                    if self.dispatch(head, buf).is_err() {
+                        break;
+                    }
+                }
+                Err(err) if is_timeout(&err) => break,
+                Err(err) => {
+                    return self.connection_lost(generation, err).is_ok();
+                }
+            }
+        }
+        if self.ensure_alive().is_err() {
+            return false;
+        }
+        let due = self.heartbeat.lock().unwrap().as_ref().filter(|hb| hb.is_due()).map(|hb| hb.interval);
+        if let Some(interval) = due {
+            if let Err((err, _)) = self.writer.write(Heartbeat::frame(self.host.clone(), interval).into()) {
+                if !is_timeout(&err) {
+                    return self.connection_lost(generation, err).is_ok();
+                }
+            }
+            if let Some(hb) = self.heartbeat.lock().unwrap().as_mut() {
+                hb.last_sent = Instant::now();
+            }
+        }
+        true
+    }
+
+    // `vhost` defaults to the session's virtual host. Frames are told apart by channel name only,
+    // so a name can be in use on one virtual host at a time.
+    pub fn create_channel(&mut self, name: String, vhost: Option<String>) -> Option<Arc<RwLock<Channel>>> {
+        // the name of a closing channel stays taken until the broker confirmed the close
+        if self.channels.get(&name).is_some_and(|status| !matches!(status.get(), ChannelState::Closed | ChannelState::Failed)) {
             return None;
         }
-        let channel =Arc::from(RwLock::from(Channel::new(self.host.clone(), name.clone(), self.self_ref.clone()?)));
-        self.cache.insert(name.clone(), VecDeque::new());
-        self.channels.insert(name.clone(), channel);
-        self.channels.get_mut(&name).cloned()
+        let host = vhost.unwrap_or_else(|| self.host.clone());
+        let status = ChannelStatus::new(host, name.clone());
+        let session = self.self_ref.as_ref()?.upgrade()?;
+        let handle = ChannelHandle::new(status.clone(), self.writer.clone(), self.config.slice_size, self.unacked.clone(), self.confirms.clone(), self.config.events.clone());
+        let channel = Channel::new(handle, session);
+        self.cache.lock().unwrap().insert(name.clone(), ChannelCache::new(self.config.cache_limit));
+        self.channels.insert(name.clone(), status);
+        self.config.events.emit(SessionEvent::ChannelOpened { host: channel.host_name().clone(), channel: name });
+        Some(Arc::from(RwLock::from(channel)))
     }
 
+    pub fn channel_state(&self, name: &String) -> Option<ChannelState> {
+        self.channels.get(name).map(ChannelStatus::get)
+    }
+
+    // Closes the channel like Channel::close, its status stays behind until the broker confirmed
+    // the close. A close that cannot be sent fails the channel, so that its name is free again.
     pub fn drop_channel(&mut self, name: String) {
-        let ch = self.channels.get_mut(&name).unwrap();
-        if !ch.write().unwrap().is_closed() {
-            ch.write().unwrap().close();
+        if let Some(status) = self.channels.get(&name) {
+            if self.send_close(status).is_err() {
+                self.set_closed(status, ChannelState::Failed);
+            }
         }
-        self.cache.remove(&name);
-        self.channels.remove(&name);
+        self.cache.lock().unwrap().remove(&name);
+        self.cache_space.notify_all();
     }
 
     pub fn drop_all_channels(&mut self) {
-        self.channels.iter_mut().for_each(|(_, ch)| { ch.write().unwrap().close(); })
+        let names = self.channels.keys().cloned().collect::<Vec<_>>();
+        names.into_iter().for_each(|name| self.drop_channel(name));
+    }
+
+    pub fn close(&mut self) -> CloseReport {
+        self.close_timeout(Duration::from_millis(3000))
     }
 
-    pub fn close(&mut self) {
+    // flushes, closes every open channel and waits up to `deadline` for the broker to confirm
+    // each close before the socket is shut down
+    pub fn close_timeout(&mut self, deadline: Duration) -> CloseReport {
+        let mut report = CloseReport::new();
+        self.disable_heartbeat();
+        let deadline = Instant::now() + deadline;
+
+        if let Err(err) = self.writer.flush() {
+            report.flush_error = Some(err);
+        }
+
+        let mut pending = vec![];
+        if !self.is_disconnected() {
+            for status in self.channels.values() {
+                match self.send_close(status) {
+                    Ok(true) => pending.push(status.name.clone()),
+                    // closed earlier but not confirmed yet, worth waiting for as well
+                    Ok(false) if status.get() == ChannelState::Closing => pending.push(status.name.clone()),
+                    Ok(false) => {}
+                    Err(err) => report.failed.push((status.name.clone(), err)),
+                }
+            }
+        }
+
+        let mut reader = self.reader.lock().unwrap();
+        while !pending.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let _ = reader.set_read_timeout(Some(remaining.min(self.config.read_timeout)));
+            match self.read_frame(&mut reader) {
+                Ok((head, _)) => {
+                    if Command::parse(&head.command) == Some(Command::CloseChannel) {
+                        let ch = String::from_utf8_lossy(&head.channel).trim_end_matches('\0').to_string();
+                        pending.retain(|name| *name != ch);
+                    }
+                }
+                Err(err) if is_timeout(&err) => {}
+                Err(err) => {
+                    pending.drain(..).for_each(|name| report.failed.push((name, std::io::Error::new(err.kind(), err.to_string()))));
+                }
+            }
+        }
+        report.unconfirmed = pending;
+
+        if let Err(err) = reader.shutdown() {
+            if err.kind() != ErrorKind::NotConnected {
+                report.shutdown_error = Some(err);
+            }
+        }
+        drop(reader);
+        self.channels.values().for_each(|status| self.set_closed(status, ChannelState::Closed));
         self.channels.clear();
-        self.drop_all_channels();
-        self.stream.write().unwrap().shutdown(std::net::Shutdown::Both).unwrap();
+        *self.disconnected.lock().unwrap() = Some(String::from("session closed"));
+        self.config.events.emit(SessionEvent::Disconnected { reason: String::from("session closed"), reconnecting: false });
+        self.cache.lock().unwrap().clear();
+        self.cache_space.notify_all();
+        report
     }
 
-    pub fn send(&self, data: Vec<u8>) -> Result<(), std::io::Error> {
-        self.stream
-            .write()
-            .unwrap()
-            .write_all(data.as_slice())
+    pub fn send<F: Into<Frame>>(&self, data: F) -> MqResult<()> {
+        self.ensure_alive()?;
+        let frame = data.into();
+        if frame.len().saturating_sub(256) > self.config.max_frame_size {
+            return Err(MqError::Validation(
+                format!("frame payload exceeds max_frame_size of {} bytes", self.config.max_frame_size)
+            ));
+        }
+        let generation = self.generation.load(Ordering::SeqCst);
+        match self.writer.write(frame) {
+            Err((err, data)) if !is_timeout(&err) => {
+                self.connection_lost(generation, err)?;
+                self.writer.write(data).map_err(|(err, _)| err.into())
+            }
+            result => result.map_err(|(err, _)| err.into())
+        }
     }
 
-    pub fn read(&mut self, channel: &String) -> Result<(Option<DataHead>, Box<Vec<u8>>), Box<dyn Error>> {
-        let mut buf_head = [0u8; 256];
-        let result = self.stream.write().unwrap().read_exact(&mut buf_head).is_ok();
-        if result {
-            let head = DataHead::deserialize(buf_head);
-            let count = head.slice_count;
-            let size = head.slice_size;
-            let mut buf = vec![];
-            for _ in 0..count {
-                let mut buf_slice = vec![0u8; size as usize];
-                self.stream.write().unwrap().read_exact(&mut buf_slice)?;
-                buf.append(&mut buf_slice);
-            }
-
-            let ch = String::from_utf8(head.channel.to_vec())?.trim_end_matches('\0').to_string();
-            return if ch == channel.clone() {
-                Ok((Some(head), Box::from(buf)))
-            } else {
-                self.cache.get_mut(&ch).unwrap().push_back(Box::from(buf));
-                let cached = self.cache.get_mut(channel).unwrap().pop_front();
-                if let Some(c) = cached {
-                    Ok((None, c))
-                } else {
-                    Err("read error".into())
+    pub fn declare(&self, declaration: Declaration) -> MqResult<()> {
+        self.send(declaration.build())?;
+        self.topology.lock().unwrap().record(declaration);
+        Ok(())
+    }
+
+    pub fn host(&self) -> &String {
+        &self.host
+    }
+
+    pub fn create_virtual_host(&self, name: String) -> MqResult<()> {
+        self.declare(Declaration::virtual_host(CommandType::NewVirtualHost, name))
+    }
+
+    // everything declared on the virtual host goes with it
+    pub fn drop_virtual_host(&self, name: String) -> MqResult<()> {
+        self.declare(Declaration::virtual_host(CommandType::DropVirtualHost, name))
+    }
+
+    pub fn read(&self, channel: &String) -> MqResult<(DataHead, Payload)> {
+        self.check_channel(channel)?;
+        self.ensure_alive()?;
+        if let Some(cached) = self.pop_cached(channel)? {
+            return Ok(cached);
+        }
+        let generation = self.generation.load(Ordering::SeqCst);
+        let failed = {
+            let mut reader = self.reader.lock().unwrap();
+            // another reader may have picked up our frame while we were waiting for the stream
+            if let Some(cached) = self.pop_cached(channel)? {
+                return Ok(cached);
+            }
+            let frame = loop {
+                match self.read_frame(&mut reader) {
+                    Ok((head, _)) if Command::parse(&head.command) == Some(Command::Heartbeat) => continue,
+                    frame => break frame
                 }
             };
-            Ok((Some(head), Box::from(buf)))
-        } else {
-            if let Some(cache) = self.cache.get_mut(channel)
-                .unwrap()
-                .pop_front() {
-                Ok((None, cache))
-            } else {
-                Err("read error".into())
+            match frame {
+                Ok((head, buf)) => {
+                    let ch = String::from_utf8(head.channel.to_vec())?.trim_end_matches('\0').to_string();
+                    if ch == channel.clone() && Command::parse(&head.command) != Some(Command::CloseChannel) && !confirm::is_confirm(&head) {
+                        return Ok((head, buf));
+                    }
+                    // cached before the stream is let go, a frame read after it must not get there first
+                    self.dispatch(head, buf)?;
+                    None
+                }
+                Err(err) if is_timeout(&err) => None,
+                Err(err) => Some(err),
             }
+        };
+        if let Some(err) = failed {
+            // whatever was in flight on the dead connection is lost, the caller has to retry
+            self.connection_lost(generation, err)?;
+        }
+
+        if let Some(cached) = self.pop_cached(channel)? {
+            Ok(cached)
+        } else {
+            // the broker may just have closed the channel
+            self.check_channel(channel)?;
+            Err(MqError::Timeout)
         }
     }
 
-    pub fn send_and_read(&mut self, data: Vec<u8>, channel: &String) -> Result<(Option<DataHead>, Box<Vec<u8>>), Box<dyn Error>> {
-        self.stream
-            .write()
-            .unwrap()
-            .write_all(data.as_slice())?;
+    // Reads and dispatches a frame if one has arrived and nobody else is reading, otherwise waits
+    // up to `wait` for another reader to settle a publish.
+    pub fn pump(&self, wait: Duration) -> MqResult<()> {
+        self.ensure_alive()?;
+        let generation = self.generation.load(Ordering::SeqCst);
+        let frame = {
+            let Ok(mut reader) = self.reader.try_lock() else {
+                self.confirms.wait(wait);
+                return Ok(());
+            };
+            if !reader.poll_ready(256).unwrap_or(true) {
+                drop(reader);
+                self.confirms.wait(wait);
+                return Ok(());
+            }
+            match self.read_frame(&mut reader) {
+                Ok((head, buf)) => return self.dispatch(head, buf),
+                frame => frame
+            }
+        };
+        match frame {
+            Err(err) if !is_timeout(&err) => Ok(self.connection_lost(generation, err)?),
+            _ => Ok(()),
+        }
+    }
 
+    pub fn send_and_read<F: Into<Frame>>(&self, data: F, channel: &String) -> MqResult<(DataHead, Payload)> {
+        self.send(data)?;
         self.read(channel)
     }
-}
\ No newline at end of file
+
+    pub fn reconnect(&self) -> MqResult<()> {
+        Ok(self.reconnect_from(self.generation.load(Ordering::SeqCst), String::from("reconnect requested"))?)
+    }
+
+    fn reconnect_from(&self, generation: u64, reason: String) -> Result<(), std::io::Error> {
+        let policy = self.config.reconnect_policy.clone().ok_or(std::io::Error::new(
+            ErrorKind::NotConnected,
+            "reconnect is disabled for this session"
+        ))?;
+        let _guard = self.reconnect_lock.lock().unwrap();
+        if self.generation.load(Ordering::SeqCst) != generation {
+            // somebody else already replaced the connection we saw failing
+            return self.ensure_alive();
+        }
+        let _ = self.control.lock().unwrap().shutdown();
+        self.connector.mark_failed();
+        self.config.events.emit(SessionEvent::Disconnected { reason, reconnecting: true });
+
+        let mut attempt = 0;
+        loop {
+            match self.connect().and_then(|_| self.recover()) {
+                Ok(()) => {
+                    self.generation.fetch_add(1, Ordering::SeqCst);
+                    *self.disconnected.lock().unwrap() = None;
+                    if let Some(hb) = self.heartbeat.lock().unwrap().as_mut() {
+                        hb.last_sent = Instant::now();
+                        hb.last_received = Instant::now();
+                    }
+                    self.config.events.emit(SessionEvent::Connected);
+                    self.config.events.emit(SessionEvent::Reconnected { attempts: attempt + 1 });
+                    return Ok(());
+                }
+                Err(err) if AuthError::find(&err).is_some() => {
+                    // retrying with the same credentials cannot succeed
+                    self.mark_disconnected(err.to_string());
+                    return Err(err);
+                }
+                Err(err) => {
+                    attempt += 1;
+                    if !policy.should_retry(attempt) {
+                        let reason = format!("reconnect failed after {attempt} attempts: {err}");
+                        self.mark_disconnected(reason.clone());
+                        return Err(Disconnected::io(reason));
+                    }
+                    std::thread::sleep(policy.delay(attempt - 1));
+                }
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<(), std::io::Error> {
+        let stream = self.connector.connect()?;
+        stream.set_read_timeout(Some(self.config.read_timeout))?;
+        stream.set_write_timeout(Some(self.config.write_timeout))?;
+        self.writer.swap(stream.try_clone()?)?;
+        *self.control.lock().unwrap() = stream.try_clone()?;
+        *self.reader.lock().unwrap() = stream;
+        Ok(())
+    }
+
+    // re-open every channel that was open before the connection dropped and replay the declared topology
+    fn recover(&self) -> Result<(), std::io::Error> {
+        // channels are implicit on the wire, dropping the stale frames is all it takes to re-open them
+        for status in self.channels.values() {
+            status.transition(&[ChannelState::Open], ChannelState::Opening);
+            // the old connection took any pending close with it
+            status.transition(&[ChannelState::Closing], ChannelState::Closed);
+        }
+        self.unacked.connection_lost(&self.config.events);
+        self.confirms.connection_lost();
+        {
+            let mut caches = self.cache.lock().unwrap();
+            caches.retain(|name, _| self.channels.get(name).is_none_or(|status| status.get().is_usable()));
+            caches.values_mut().for_each(ChannelCache::clear);
+        }
+        self.cache_space.notify_all();
+        self.authenticate()?;
+        let declarations = self.topology.lock().unwrap().declarations().clone();
+        for declaration in declarations.iter() {
+            self.writer.write(declaration.build().into()).map_err(|(err, _)| err)?;
+        }
+        for status in self.channels.values() {
+            status.transition(&[ChannelState::Opening], ChannelState::Open);
+        }
+        Ok(())
+    }
+
+    // sends CLOSE-CH unless the channel is closing already, true if it was sent
+    fn send_close(&self, status: &ChannelStatus) -> Result<bool, std::io::Error> {
+        if !status.begin_close() {
+            return Ok(false);
+        }
+        for data in self.unacked.requeue_frames(&status.host, &status.name, self.config.slice_size, &self.config.events) {
+            self.writer.write(data).map_err(|(err, _)| err)?;
+        }
+        // not Channel::get_factory, that would need the session lock our caller may be holding
+        let data = self.factory(status.host.clone(), status.name.clone()).command(Command::CloseChannel).build_frame();
+        self.writer.write(data).map_err(|(err, _)| err)?;
+        Ok(true)
+    }
+
+    fn check_channel(&self, channel: &String) -> MqResult<()> {
+        if let Some(status) = self.channels.get(channel) {
+            status.check()?;
+        }
+        Ok(())
+    }
+
+    // the broker confirmed our CLOSE-CH or closed the channel on its own
+    fn channel_closed(&self, channel: &String) {
+        if let Some(status) = self.channels.get(channel) {
+            self.set_closed(status, ChannelState::Closed);
+        }
+        self.unacked.release(channel, &self.config.events);
+        self.confirms.release(channel);
+        self.cache.lock().unwrap().remove(channel);
+        self.cache_space.notify_all();
+    }
+
+    // Closed or Failed, with an event unless the channel was already there
+    fn set_closed(&self, status: &ChannelStatus, state: ChannelState) {
+        if status.transition(&[ChannelState::Opening, ChannelState::Open, ChannelState::Closing], state) {
+            self.config.events.emit(SessionEvent::ChannelClosed { channel: status.name.clone(), state });
+        }
+    }
+
+    fn mark_disconnected(&self, reason: String) {
+        *self.disconnected.lock().unwrap() = Some(reason.clone());
+        for status in self.channels.values() {
+            self.set_closed(status, ChannelState::Failed);
+        }
+        self.unacked.connection_lost(&self.config.events);
+        self.confirms.connection_lost();
+        self.config.events.emit(SessionEvent::Disconnected { reason, reconnecting: false });
+    }
+
+    fn ensure_alive(&self) -> Result<(), std::io::Error> {
+        if let Some(reason) = self.disconnected.lock().unwrap().as_ref() {
+            return Err(Disconnected::io(reason.clone()));
+        }
+        let generation = self.generation.load(Ordering::SeqCst);
+        let dead = self.heartbeat.lock().unwrap().as_ref().filter(|hb| hb.is_dead()).map(|hb| hb.missed());
+        if let Some(missed) = dead {
+            let reason = format!("missed {missed} heartbeats");
+            return self.connection_lost(generation, std::io::Error::new(ErrorKind::TimedOut, reason));
+        }
+        Ok(())
+    }
+
+    // reconnects if the session is allowed to, otherwise marks it dead for good
+    fn connection_lost(&self, generation: u64, err: std::io::Error) -> Result<(), std::io::Error> {
+        if self.config.reconnect_policy.is_some() {
+            return self.reconnect_from(generation, err.to_string());
+        }
+        let reason = err.to_string();
+        let _ = self.control.lock().unwrap().shutdown();
+        self.mark_disconnected(reason.clone());
+        Err(Disconnected::io(reason))
+    }
+
+    fn pop_cached(&self, channel: &String) -> MqResult<Option<CachedFrame>> {
+        let mut caches = self.cache.lock().unwrap();
+        let Some(cache) = caches.get_mut(channel) else {
+            return Ok(None);
+        };
+        if cache.take_overflow() {
+            return Err(MqError::CacheOverflow(channel.clone()));
+        }
+        let cached = cache.pop();
+        if cached.is_some() {
+            self.cache_space.notify_all();
+        }
+        Ok(cached)
+    }
+
+    fn dispatch(&self, head: DataHead, buf: Payload) -> MqResult<()> {
+        match Command::parse(&head.command) {
+            Some(Command::Heartbeat) => {
+                *self.heartbeat_reply.lock().unwrap() = Some(Heartbeat::parse_interval(&buf));
+                self.heartbeat_ready.notify_all();
+                return Ok(());
+            }
+            Some(Command::Auth) => {
+                *self.auth_reply.lock().unwrap() = Some(head.errcode);
+                self.auth_ready.notify_all();
+                return Ok(());
+            }
+            _ => {}
+        }
+        let ch = String::from_utf8(head.channel.to_vec())?.trim_end_matches('\0').to_string();
+        if Command::parse(&head.command) == Some(Command::CloseChannel) {
+            self.channel_closed(&ch);
+            return Ok(());
+        }
+        if confirm::is_confirm(&head) {
+            self.confirms.settle(&ch, head.delivery_tag(), confirm::confirmation(&head));
+            return Ok(());
+        }
+        // late replies for a channel that is going away are not worth keeping
+        if self.channels.get(&ch).is_some_and(|status| !status.get().is_usable()) {
+            self.config.events.emit(SessionEvent::FrameDropped { channel: ch, reason: DropReason::ChannelClosed });
+            return Ok(());
+        }
+        let mut caches = self.cache.lock().unwrap();
+        if !caches.contains_key(&ch) {
+            drop(caches);
+            self.config.events.emit(SessionEvent::UnknownChannel { channel: ch });
+            return Ok(());
+        }
+        let mut frame = (head, buf);
+        let mut blocked = false;
+        let mut events = vec![];
+        // the loop only repeats for a full cache under OverflowPolicy::Block
+        while let Some(cache) = caches.get_mut(&ch) {
+            let policy = cache.policy();
+            if cache.is_full() && policy != OverflowPolicy::Block {
+                events.push(SessionEvent::CacheOverflow { channel: ch.clone(), policy });
+                let reason = if policy == OverflowPolicy::DropOldest { DropReason::Evicted } else { DropReason::CacheFull };
+                events.push(SessionEvent::FrameDropped { channel: ch.clone(), reason });
+            }
+            match cache.push(frame) {
+                None => break,
+                Some(rejected) => {
+                    frame = rejected;
+                    if !blocked {
+                        cache.record_blocked();
+                        blocked = true;
+                        // listeners must not run under the cache lock
+                        drop(caches);
+                        self.config.events.emit(SessionEvent::CacheOverflow { channel: ch.clone(), policy });
+                        caches = self.cache.lock().unwrap();
+                        continue;
+                    }
+                    if self.is_disconnected() {
+                        break;
+                    }
+                    caches = self.cache_space.wait_timeout(caches, Duration::from_millis(100)).unwrap().0;
+                }
+            }
+        }
+        drop(caches);
+        events.into_iter().for_each(|event| self.config.events.emit(event));
+        Ok(())
+    }
+
+    // reads every slice of the frame straight into one pooled buffer
+    fn read_frame(&self, reader: &mut Box<dyn Transport>) -> Result<(DataHead, Payload), std::io::Error> {
+        let mut buf_head = [0u8; 256];
+        reader.read_exact(&mut buf_head)?;
+        if let Some(hb) = self.heartbeat.lock().unwrap().as_mut() {
+            hb.last_received = Instant::now();
+        }
+        let head = DataHead::deserialize(buf_head);
+        let len = head.slice_count as usize * head.slice_size as usize;
+        if len > self.config.max_frame_size {
+            // the payload is never read, so the stream cannot be trusted any more
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("incoming frame of {len} bytes exceeds max_frame_size of {} bytes", self.config.max_frame_size)
+            ));
+        }
+        let mut buf = self.pool.take(len);
+        reader.read_exact(&mut buf).map_err(|err| if is_timeout(&err) {
+            // a frame cut in half leaves the stream out of sync, treat it as a broken connection
+            std::io::Error::new(ErrorKind::UnexpectedEof, err)
+        } else {
+            err
+        })?;
+        Ok((head, self.pool.payload(buf)))
+    }
+}
+
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+// a zero timeout means blocking forever to some streams and an error to others
+fn positive(timeout: Duration, which: &str) -> Result<Duration, std::io::Error> {
+    if timeout.is_zero() {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("the {which} timeout must be longer than zero")));
+    }
+    Ok(timeout)
+}
+
+#[derive(Debug)]
+pub struct CloseReport {
+    pub flush_error: Option<std::io::Error>,
+    pub failed: Vec<(String, std::io::Error)>,
+    pub unconfirmed: Vec<String>,
+    pub shutdown_error: Option<std::io::Error>,
+}
+
+impl CloseReport {
+    fn new() -> CloseReport {
+        CloseReport {
+            flush_error: None,
+            failed: vec![],
+            unconfirmed: vec![],
+            shutdown_error: None,
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.flush_error.is_none()
+            && self.failed.is_empty()
+            && self.unconfirmed.is_empty()
+            && self.shutdown_error.is_none()
+    }
+}
@@ -0,0 +1,97 @@
+use crate::mq::io::factory::{CommandType, DataType, MessageFactory, Routing, RoutingModFactory, RoutingType};
+use crate::mq::routing::chain::RoutingChain;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    pub host: String,
+    pub command_type: CommandType,
+    pub channel: String,
+    pub name: String,
+    pub routing_chain: RoutingChain,
+}
+
+impl Declaration {
+    pub fn new(host: String, command_type: CommandType, channel: String, name: String, routing_chain: RoutingChain) -> Declaration {
+        Declaration {
+            host,
+            command_type,
+            channel,
+            name,
+            routing_chain,
+        }
+    }
+
+    // virtual hosts are declared on themselves, outside of any channel
+    pub fn virtual_host(command_type: CommandType, name: String) -> Declaration {
+        Declaration::new(name.clone(), command_type, String::new(), name, RoutingChain::new([const { Routing::Stop }; 3], String::new()))
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let routing_mod = RoutingModFactory::new()
+            .routing_type(RoutingType::Direct)
+            .data_type(DataType::Command)
+            .command_type(self.command_type)
+            .build();
+        MessageFactory::new(self.host.clone(), self.channel.clone())
+            .routing_mod(routing_mod)
+            .routing_chain(self.routing_chain.clone())
+            .data(self.name.clone().into_bytes())
+            .build()
+    }
+
+    fn declares(&self, other: &Declaration) -> bool {
+        self.host == other.host && self.name == other.name && match self.command_type {
+            // bindings are identified by their routing chain as well as their name
+            CommandType::NewBinding | CommandType::DropBinding => self.routing_chain == other.routing_chain,
+            _ => true
+        }
+    }
+}
+
+// Everything declared through ChannelApi on a session, in declaration order,
+// so that it can be replayed against a fresh connection.
+pub struct Topology {
+    declarations: Vec<Declaration>,
+}
+
+impl Topology {
+    pub fn new() -> Topology {
+        Topology {
+            declarations: vec![],
+        }
+    }
+
+    pub fn record(&mut self, declaration: Declaration) {
+        let created = match declaration.command_type {
+            CommandType::DropQueue => CommandType::NewQueue,
+            CommandType::DropExchange => CommandType::NewExchange,
+            CommandType::DropBinding => CommandType::NewBinding,
+            CommandType::DropVirtualHost => {
+                self.declarations.retain(|d| d.host != declaration.name);
+                return;
+            }
+            CommandType::Nop => return,
+            _ => {
+                if !self.declarations.contains(&declaration) {
+                    self.declarations.push(declaration);
+                }
+                return;
+            }
+        };
+        self.declarations.retain(|d| !(d.command_type == created && d.declares(&declaration)));
+    }
+
+    pub fn declarations(&self) -> &Vec<Declaration> {
+        &self.declarations
+    }
+
+    pub fn clear(&mut self) {
+        self.declarations.clear();
+    }
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Topology::new()
+    }
+}
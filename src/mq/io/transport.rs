@@ -0,0 +1,381 @@
+use std::collections::VecDeque;
+use std::io::{ErrorKind, IoSlice, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// How long poll_ready waits for data that has not arrived yet. The socket is never switched to
+// nonblocking for a poll, the flag is shared with the clones that write.
+const POLL_WAIT: Duration = Duration::from_millis(1);
+
+pub trait Transport: Read + Write + Send + Sync {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error>;
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error>;
+
+    fn shutdown(&self) -> Result<(), std::io::Error>;
+
+    // true if `len` bytes can be read without blocking, or if the next read would fail
+    fn poll_ready(&mut self, len: usize) -> Result<bool, std::io::Error>;
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>, std::io::Error>;
+}
+
+pub trait Connector: Send + Sync {
+    fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error>;
+
+    // the connection handed out last has broken, connectors with several endpoints move on
+    fn mark_failed(&self) {}
+}
+
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> TcpTransport {
+        TcpTransport { stream }
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        self.stream.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        self.stream.set_write_timeout(timeout)
+    }
+
+    fn shutdown(&self) -> Result<(), std::io::Error> {
+        self.stream.shutdown(std::net::Shutdown::Both)
+    }
+
+    fn poll_ready(&mut self, len: usize) -> Result<bool, std::io::Error> {
+        let timeout = self.stream.read_timeout()?;
+        self.stream.set_read_timeout(Some(POLL_WAIT))?;
+        let mut buf = vec![0u8; len];
+        let ready = match self.stream.peek(&mut buf) {
+            Ok(n) => n == 0 || n == len,
+            Err(err) => !matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        };
+        self.stream.set_read_timeout(timeout)?;
+        Ok(ready)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        Ok(Box::new(TcpTransport::new(self.stream.try_clone()?)))
+    }
+}
+
+pub struct TcpConnector {
+    addrs: Vec<SocketAddr>,
+}
+
+impl TcpConnector {
+    pub fn new<A: ToSocketAddrs>(addr: A) -> Result<TcpConnector, std::io::Error> {
+        Ok(TcpConnector {
+            addrs: addr.to_socket_addrs()?.collect(),
+        })
+    }
+}
+
+impl Connector for TcpConnector {
+    fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        Ok(Box::new(TcpTransport::new(TcpStream::connect(self.addrs.as_slice())?)))
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixTransport {
+    stream: std::os::unix::net::UnixStream,
+    // bytes pulled off the socket by poll_ready, served before the socket itself
+    read_ahead: VecDeque<u8>,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    pub fn new(stream: std::os::unix::net::UnixStream) -> UnixTransport {
+        UnixTransport {
+            stream,
+            read_ahead: VecDeque::new(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Read for UnixTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.read_ahead.is_empty() {
+            return self.stream.read(buf);
+        }
+        self.read_ahead.read(buf)
+    }
+}
+
+#[cfg(unix)]
+impl Write for UnixTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        self.stream.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        self.stream.set_write_timeout(timeout)
+    }
+
+    fn shutdown(&self) -> Result<(), std::io::Error> {
+        self.stream.shutdown(std::net::Shutdown::Both)
+    }
+
+    fn poll_ready(&mut self, len: usize) -> Result<bool, std::io::Error> {
+        if self.read_ahead.len() >= len {
+            return Ok(true);
+        }
+        let timeout = self.stream.read_timeout()?;
+        self.stream.set_read_timeout(Some(POLL_WAIT))?;
+        let mut buf = vec![0u8; len - self.read_ahead.len()];
+        let ready = match self.stream.read(&mut buf) {
+            Ok(0) => true,
+            Ok(n) => {
+                self.read_ahead.extend(&buf[..n]);
+                self.read_ahead.len() >= len
+            }
+            Err(err) => !matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        };
+        self.stream.set_read_timeout(timeout)?;
+        Ok(ready)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        // the read-ahead buffer stays with this handle, clones are meant for writing
+        Ok(Box::new(UnixTransport::new(self.stream.try_clone()?)))
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixConnector {
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> UnixConnector {
+        UnixConnector {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Connector for UnixConnector {
+    fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        Ok(Box::new(UnixTransport::new(std::os::unix::net::UnixStream::connect(&self.path)?)))
+    }
+}
+
+struct PipeState {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+// one direction of an in-memory connection
+struct Pipe {
+    state: Mutex<PipeState>,
+    ready: Condvar,
+}
+
+impl Pipe {
+    fn new() -> Arc<Pipe> {
+        Arc::new(Pipe {
+            state: Mutex::new(PipeState {
+                data: VecDeque::new(),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        })
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+// In-memory duplex pipe, behaves like a connected socket pair including read timeouts.
+pub struct MemoryTransport {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Arc<Mutex<Option<Duration>>>,
+}
+
+impl MemoryTransport {
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let a = Pipe::new();
+        let b = Pipe::new();
+        (
+            MemoryTransport {
+                incoming: a.clone(),
+                outgoing: b.clone(),
+                read_timeout: Arc::new(Mutex::new(None)),
+            },
+            MemoryTransport {
+                incoming: b,
+                outgoing: a,
+                read_timeout: Arc::new(Mutex::new(None)),
+            },
+        )
+    }
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let timeout = *self.read_timeout.lock().unwrap();
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut state = self.incoming.state.lock().unwrap();
+        while state.data.is_empty() && !state.closed {
+            state = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(std::io::Error::new(ErrorKind::WouldBlock, "read timed out"));
+                    }
+                    self.incoming.ready.wait_timeout(state, remaining).unwrap().0
+                }
+                None => self.incoming.ready.wait(state).unwrap(),
+            };
+        }
+        state.data.read(buf)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(std::io::Error::new(ErrorKind::BrokenPipe, "memory transport is closed"));
+        }
+        state.data.extend(buf);
+        self.outgoing.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(std::io::Error::new(ErrorKind::BrokenPipe, "memory transport is closed"));
+        }
+        bufs.iter().for_each(|buf| state.data.extend(buf.iter()));
+        self.outgoing.ready.notify_all();
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        *self.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        // writes never block
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<(), std::io::Error> {
+        self.incoming.close();
+        self.outgoing.close();
+        Ok(())
+    }
+
+    fn poll_ready(&mut self, len: usize) -> Result<bool, std::io::Error> {
+        let state = self.incoming.state.lock().unwrap();
+        Ok(state.closed || state.data.len() >= len)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        Ok(Box::new(MemoryTransport {
+            incoming: self.incoming.clone(),
+            outgoing: self.outgoing.clone(),
+            read_timeout: self.read_timeout.clone(),
+        }))
+    }
+}
+
+// Accepting end of in-memory connections, every connect() hands a fresh pair to the listener.
+pub struct MemoryListener {
+    accepted: Receiver<MemoryTransport>,
+}
+
+pub struct MemoryConnector {
+    listener: Mutex<Sender<MemoryTransport>>,
+}
+
+impl MemoryListener {
+    pub fn bind() -> (MemoryListener, MemoryConnector) {
+        let (tx, rx) = channel();
+        (
+            MemoryListener { accepted: rx },
+            MemoryConnector { listener: Mutex::new(tx) },
+        )
+    }
+
+    pub fn accept(&self) -> Result<MemoryTransport, std::io::Error> {
+        self.accepted.recv().map_err(|_| std::io::Error::new(ErrorKind::NotConnected, "memory connector dropped"))
+    }
+
+    pub fn accept_timeout(&self, timeout: Duration) -> Result<MemoryTransport, std::io::Error> {
+        self.accepted.recv_timeout(timeout).map_err(|_| std::io::Error::new(ErrorKind::TimedOut, "no pending connection"))
+    }
+}
+
+impl Connector for MemoryConnector {
+    fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        let (client, server) = MemoryTransport::pair();
+        self.listener
+            .lock()
+            .unwrap()
+            .send(server)
+            .map_err(|_| std::io::Error::new(ErrorKind::ConnectionRefused, "memory listener dropped"))?;
+        Ok(Box::new(client))
+    }
+}
@@ -0,0 +1,209 @@
+use crate::mq::io::frame::Frame;
+use crate::mq::io::transport::Transport;
+use std::io::{ErrorKind, IoSlice};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// a failed write hands the frame back to the caller
+pub type WriteResult = Result<(), (std::io::Error, Frame)>;
+
+pub enum WriteRequest {
+    Frame(Frame, Option<SyncSender<WriteResult>>),
+    Flush(SyncSender<Result<(), std::io::Error>>),
+    Swap(Box<dyn Transport>),
+    Configure(WriterConfig),
+}
+
+// A batch is written out as soon as any of the limits is hit. With the default
+// zero linger only frames that are already queued get coalesced, so latency is unchanged.
+#[derive(Debug, Clone)]
+pub struct WriterConfig {
+    pub max_batch_bytes: usize,
+    pub max_batch_frames: usize,
+    pub linger: Duration,
+}
+
+impl WriterConfig {
+    pub fn new() -> WriterConfig {
+        WriterConfig {
+            max_batch_bytes: 64 * 1024,
+            max_batch_frames: 64,
+            linger: Duration::ZERO,
+        }
+    }
+
+    pub fn max_batch_bytes(mut self, max_batch_bytes: usize) -> WriterConfig {
+        self.max_batch_bytes = max_batch_bytes.max(1);
+        self
+    }
+
+    pub fn max_batch_frames(mut self, max_batch_frames: usize) -> WriterConfig {
+        // each frame takes three iovecs, stay well below IOV_MAX
+        self.max_batch_frames = max_batch_frames.clamp(1, 256);
+        self
+    }
+
+    pub fn linger(mut self, linger: Duration) -> WriterConfig {
+        self.linger = linger;
+        self
+    }
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        WriterConfig::new()
+    }
+}
+
+// Owns the write half of the connection on its own thread, so producers
+// only ever queue behind other producers and never behind a blocked reader.
+#[derive(Clone)]
+pub struct Writer {
+    tx: Sender<WriteRequest>,
+}
+
+impl Writer {
+    pub fn spawn(transport: Box<dyn Transport>, config: WriterConfig) -> Writer {
+        let (tx, rx) = channel();
+        thread::spawn(move || Writer::run(transport, config, rx));
+        Writer { tx }
+    }
+
+    // returns the frame back on failure so that it can be retried on a new connection
+    pub fn write(&self, frame: Frame) -> WriteResult {
+        let (reply_tx, reply_rx) = sync_channel(1);
+        if let Err(err) = self.tx.send(WriteRequest::Frame(frame, Some(reply_tx))) {
+            let WriteRequest::Frame(frame, _) = err.0 else { unreachable!() };
+            return Err((writer_gone(), frame));
+        }
+        reply_rx.recv().unwrap_or_else(|_| Err((writer_gone(), Frame::from(vec![]))))
+    }
+
+    pub fn write_nowait(&self, frame: Frame) -> Result<(), std::io::Error> {
+        self.tx.send(WriteRequest::Frame(frame, None)).map_err(|_| writer_gone())
+    }
+
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        let (reply_tx, reply_rx) = sync_channel(1);
+        self.tx.send(WriteRequest::Flush(reply_tx)).map_err(|_| writer_gone())?;
+        reply_rx.recv().map_err(|_| writer_gone())?
+    }
+
+    pub fn swap(&self, transport: Box<dyn Transport>) -> Result<(), std::io::Error> {
+        self.tx.send(WriteRequest::Swap(transport)).map_err(|_| writer_gone())
+    }
+
+    pub fn configure(&self, config: WriterConfig) -> Result<(), std::io::Error> {
+        self.tx.send(WriteRequest::Configure(config)).map_err(|_| writer_gone())
+    }
+
+    fn run(mut transport: Box<dyn Transport>, mut config: WriterConfig, rx: Receiver<WriteRequest>) {
+        let mut batch: Vec<(Frame, Option<SyncSender<WriteResult>>)> = vec![];
+        let mut bytes = 0;
+        // a request that ended a batch early, handled right after the batch went out
+        let mut deferred = None;
+
+        // the thread ends once the session and every channel dropped their Writer
+        while let Some(request) = deferred.take().or_else(|| rx.recv().ok()) {
+            match request {
+                WriteRequest::Frame(frame, reply) => {
+                    // a caller blocked on its write is not kept waiting for the linger,
+                    // the batch only picks up what is already queued
+                    let mut deadline = if reply.is_some() { Instant::now() } else { Instant::now() + config.linger };
+                    bytes += frame.len();
+                    batch.push((frame, reply));
+                    while batch.len() < config.max_batch_frames && bytes < config.max_batch_bytes {
+                        let next = match deadline.checked_duration_since(Instant::now()) {
+                            Some(remaining) if !remaining.is_zero() => rx.recv_timeout(remaining).map_err(|err| err == RecvTimeoutError::Disconnected),
+                            _ => rx.try_recv().map_err(|err| err == TryRecvError::Disconnected),
+                        };
+                        match next {
+                            Ok(WriteRequest::Frame(frame, reply)) => {
+                                if reply.is_some() {
+                                    deadline = Instant::now();
+                                }
+                                bytes += frame.len();
+                                batch.push((frame, reply));
+                            }
+                            Ok(request) => {
+                                deferred = Some(request);
+                                break;
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    Writer::write_batch(&mut transport, std::mem::take(&mut batch));
+                    bytes = 0;
+                }
+                WriteRequest::Flush(reply) => {
+                    let _ = reply.send(transport.flush());
+                }
+                WriteRequest::Swap(new_transport) => {
+                    transport = new_transport;
+                }
+                WriteRequest::Configure(new_config) => {
+                    config = new_config;
+                }
+            }
+        }
+    }
+
+    fn write_batch(transport: &mut Box<dyn Transport>, batch: Vec<(Frame, Option<SyncSender<WriteResult>>)>) {
+        let mut slices = batch.iter()
+            .flat_map(|(frame, _)| frame.io_slices())
+            .filter(|slice| !slice.is_empty())
+            .collect::<Vec<_>>();
+        let mut written = 0;
+        let mut result = Ok(());
+        let mut remaining: &mut [IoSlice] = &mut slices;
+        while !remaining.is_empty() {
+            match transport.write_vectored(remaining) {
+                Ok(0) => {
+                    result = Err(std::io::Error::new(ErrorKind::WriteZero, "failed to write whole batch"));
+                    break;
+                }
+                Ok(n) => {
+                    written += n;
+                    IoSlice::advance_slices(&mut remaining, n);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        drop(slices);
+
+        // Half a frame on the wire puts every later frame out of step with the broker, even if only
+        // a timeout stopped the write. The connection is done for, whatever the error was.
+        let mut boundaries = batch.iter().scan(0, |offset, (frame, _)| {
+            *offset += frame.len();
+            Some(*offset)
+        });
+        if written > 0 && !boundaries.any(|offset| offset == written) {
+            if let Err(err) = result {
+                let _ = transport.shutdown();
+                result = Err(std::io::Error::new(ErrorKind::ConnectionAborted, format!("write stopped part way through a frame: {err}")));
+            }
+        }
+
+        // frames that made it out completely succeeded, everything from the first partial one failed
+        let mut offset = 0;
+        for (frame, reply) in batch {
+            offset += frame.len();
+            let frame_result = match &result {
+                Err(err) if offset > written => Err((std::io::Error::new(err.kind(), err.to_string()), frame)),
+                _ => Ok(()),
+            };
+            if let Some(reply) = reply {
+                let _ = reply.send(frame_result);
+            }
+        }
+    }
+}
+
+fn writer_gone() -> std::io::Error {
+    std::io::Error::new(ErrorKind::BrokenPipe, "writer thread has stopped")
+}
@@ -1,4 +1,5 @@
 pub mod protocol;
 pub mod routing;
 pub mod io;
-mod api;
\ No newline at end of file
+pub mod api;
+pub mod error;
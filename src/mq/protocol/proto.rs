@@ -56,6 +56,36 @@ impl DataHead {
             reserved: [0u8; 16]
         }
     }
+
+    // reserved[0..4] holds the unpadded payload length, 0 when the sender did not fill it in
+    pub fn body_len(&self) -> Option<usize> {
+        match u32::from_le_bytes(<[u8; 4]>::try_from(&self.reserved[0..4]).unwrap()) {
+            0 => None,
+            len => Some(len as usize),
+        }
+    }
+
+    pub fn set_body_len(&mut self, len: usize) {
+        self.reserved[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+    }
+
+    // reserved[4..12] holds the tag the broker gave this delivery, 0 if it does not track deliveries
+    pub fn delivery_tag(&self) -> u64 {
+        u64::from_le_bytes(<[u8; 8]>::try_from(&self.reserved[4..12]).unwrap())
+    }
+
+    pub fn set_delivery_tag(&mut self, tag: u64) {
+        self.reserved[4..12].copy_from_slice(&tag.to_le_bytes());
+    }
+
+    // reserved[12..16] asks the broker to hold an empty fetch for up to this many milliseconds
+    pub fn wait_ms(&self) -> u32 {
+        u32::from_le_bytes(<[u8; 4]>::try_from(&self.reserved[12..16]).unwrap())
+    }
+
+    pub fn set_wait_ms(&mut self, wait_ms: u32) {
+        self.reserved[12..16].copy_from_slice(&wait_ms.to_le_bytes());
+    }
 }
 
 impl Serialize<256> for DataHead {
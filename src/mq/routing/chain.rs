@@ -1,6 +1,6 @@
 use crate::mq::io::factory::Routing;
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RoutingChain {
     pub routing_key: [Routing; 3],
     pub queue_name: String,
@@ -0,0 +1,92 @@
+use std::time::Duration;
+use crate::mq::api::common::ChannelQueueApi;
+use crate::mq::error::MqError;
+use crate::mq::io::ack::AckMode;
+use crate::mq::io::config::SessionConfig;
+use crate::mq::io::events::{EventBus, SessionEvent};
+use crate::mq::io::reconnect::ReconnectPolicy;
+use crate::test::stub_broker::{stub_chain, stub_queue, stub_session, wait_for};
+
+#[test]
+pub fn ack_test() -> Result<(), Box<dyn std::error::Error>> {
+    let bus = EventBus::new();
+    let events = bus.channel();
+    let config = SessionConfig::new()
+        .reconnect_policy(Some(ReconnectPolicy::new().initial_delay(Duration::from_millis(10))))
+        .events(bus);
+    let (broker, session) = stub_session(config)?;
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    channel.write().unwrap().set_ack_mode(AckMode::Manual);
+    let queue = stub_queue(&channel, "work")?;
+    let queued = || broker.queued("MQ_HOST/work");
+    for item in ["a", "b", "c", "d"] {
+        queue.push_string(item.to_string())?;
+    }
+
+    let message = queue.fetch()?.ok_or("expected a")?;
+    assert!(message.needs_ack());
+    assert_eq!(channel.read().unwrap().unacked(), 1);
+    message.ack()?;
+    assert!(!message.needs_ack());
+    assert_eq!(channel.read().unwrap().unacked(), 0);
+    assert!(matches!(message.ack(), Err(MqError::Validation(_))));
+
+    // a requeued message comes back first, under a new tag
+    let message = queue.fetch()?.ok_or("expected b")?;
+    message.nack(true)?;
+    let again = queue.fetch()?.ok_or("expected b again")?;
+    assert_eq!(again.body_string()?, "b");
+    assert_ne!(again.delivery_tag, message.delivery_tag);
+    again.reject()?;
+    queue.fetch()?.ok_or("expected c")?.nack(false)?;
+    assert_eq!(queued(), 1);
+
+    // closing the channel hands back what it still holds
+    let held = queue.fetch()?.ok_or("expected d")?;
+    assert_eq!(queued(), 0);
+    channel.write().unwrap().close();
+    wait_for(|| queued() == 1)?;
+    assert!(matches!(held.ack(), Err(MqError::ChannelClosed(_))));
+    assert!(events.try_iter().any(|event| event == SessionEvent::Requeued { channel: "MQ_CHANNEL".to_string(), count: 1 }));
+
+    // with automatic acks there is nothing to give back
+    let auto = session.write().unwrap().create_channel("MQ_AUTO".to_string(), None).unwrap();
+    let auto_queue = auto.write().unwrap().get_queue(stub_chain("work"))?;
+    let message = auto_queue.fetch()?.ok_or("expected d again")?;
+    assert_eq!(message.body_string()?, "d");
+    assert!(!message.needs_ack());
+    message.ack()?;
+    assert!(matches!(message.nack(true), Err(MqError::Validation(_))));
+
+    // deliveries of a dropped connection are redelivered by the broker
+    auto_queue.push_string(String::from("e"))?;
+    auto.write().unwrap().set_ack_mode(AckMode::Manual);
+    let lost = auto_queue.fetch()?.ok_or("expected e")?;
+    session.read().unwrap().reconnect()?;
+    wait_for(|| queued() == 1)?;
+    assert!(!lost.needs_ack());
+    assert!(events.try_iter().any(|event| event == SessionEvent::Requeued { channel: "MQ_AUTO".to_string(), count: 1 }));
+    assert_eq!(auto_queue.fetch()?.ok_or("expected e again")?.body_string()?, "e");
+
+    drop(queue);
+    drop(auto_queue);
+    session.write().unwrap().close();
+
+    // an ack that could not be written leaves the delivery outstanding
+    let (broker, session) = stub_session(SessionConfig::new())?;
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    channel.write().unwrap().set_ack_mode(AckMode::Manual);
+    let queue = stub_queue(&channel, "work")?;
+    queue.push_string(String::from("f"))?;
+    let message = queue.fetch()?.ok_or("expected f")?;
+    broker.drop_connections();
+    assert!(message.ack().is_err());
+    assert!(message.needs_ack());
+    assert_eq!(channel.read().unwrap().unacked(), 1);
+
+    drop(queue);
+    session.write().unwrap().close();
+    println!("Ack test passed!");
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::thread;
+use std::time::Duration;
+use crate::mq::error::MqError;
+use crate::test::stub_broker::{stub_queue, stub_session, wait_for};
+
+#[test]
+pub fn adapter_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (broker, session) = stub_session(Default::default())?;
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = stub_queue(&channel, "pipeline")?;
+
+    // a sender fed from ordinary pipeline code pushes everything it gets
+    let (tx, pusher) = queue.clone().into_sender();
+    for i in 0..3 {
+        tx.send(format!("item {i}").into_bytes())?;
+    }
+    drop(tx);
+    assert_eq!(pusher.join().unwrap()?, 3);
+
+    // iterating stops once the queue stayed empty for the idle timeout
+    let items = queue.iter()
+        .idle_timeout(Some(Duration::from_millis(200)))
+        .map(|message| message?.body_string())
+        .collect::<Result<Vec<_>, MqError>>()?;
+    assert_eq!(items, vec!["item 0", "item 1", "item 2"]);
+
+    let rx = queue.clone().into_receiver();
+    queue.push_string(String::from("received"))?;
+    assert_eq!(rx.recv_timeout(Duration::from_secs(2))??.body_string()?, "received");
+    drop(rx);
+
+    // dropping the receiver of an empty queue stops its thread, which lets go of the channel
+    let idle_channel = session.write().unwrap().create_channel("MQ_IDLE".to_string(), None).unwrap();
+    let idle_rx = stub_queue(&idle_channel, "idle")?.into_receiver();
+    drop(idle_channel);
+    thread::sleep(Duration::from_millis(100));
+    assert!(broker.closed.lock().unwrap().is_empty());
+    drop(idle_rx);
+    wait_for(|| broker.closed.lock().unwrap().contains(&"MQ_IDLE".to_string()))?;
+
+    // a closed channel is reported once, then the iterator is done
+    channel.write().unwrap().close();
+    let mut messages = (&queue).into_iter();
+    assert!(matches!(messages.next(), Some(Err(MqError::ChannelClosed(_)))));
+    assert!(messages.next().is_none());
+
+    println!("Queue adapter test passed!");
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use crate::mq::io::config::SessionConfig;
+use crate::test::stub_broker::{stub_queue, stub_session};
+
+#[test]
+pub fn batch_test() -> Result<(), Box<dyn std::error::Error>> {
+    for multi_message in [false, true] {
+        // small frames, so that a packed batch has to be split
+        let config = SessionConfig::new()
+            .multi_message(multi_message)
+            .max_frame_size(1024);
+        let (broker, session) = stub_session(config)?;
+        let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+        let queue = stub_queue(&channel, "imports")?;
+
+        let batch = (0..5u8).map(|i| vec![b'a' + i; 300]).collect::<Vec<_>>();
+        queue.push_batch(batch.clone())?;
+
+        let first = queue.fetch_batch(3, Duration::from_millis(500))?;
+        let rest = queue.fetch_batch(10, Duration::from_millis(500))?;
+        let fetched = first.iter().chain(rest.iter()).map(|message| message.body().to_vec()).collect::<Vec<_>>();
+        assert_eq!(first.len(), 3);
+        assert_eq!(fetched, batch);
+
+        // one request per call with packed frames, one per message otherwise
+        let fetches = broker.fetches.load(Ordering::SeqCst);
+        assert_eq!(fetches, if multi_message { 2 } else { 3 + 10 });
+
+        assert!(queue.fetch_batch(4, Duration::from_millis(50))?.is_empty());
+        drop(queue);
+        assert!(session.write().unwrap().close().is_clean());
+    }
+    println!("Batch test passed!");
+    Ok(())
+}
+
+// replies for another channel arriving in the middle of a batch do not cost any of its messages
+#[test]
+pub fn batch_concurrent_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (_broker, session) = stub_session(SessionConfig::new().multi_message(false))?;
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = stub_queue(&channel, "imports")?;
+    let other_channel = session.write().unwrap().create_channel("MQ_OTHER".to_string(), None).unwrap();
+    let other = stub_queue(&other_channel, "exports")?;
+
+    let pushed = (0..60u8).map(|i| vec![i; 16]).collect::<Vec<_>>();
+    for body in &pushed {
+        queue.push(body.clone())?;
+    }
+
+    let stop = AtomicBool::new(false);
+    let fetched = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !stop.load(Ordering::SeqCst) {
+                other.fetch().unwrap();
+            }
+        });
+        let mut fetched = vec![];
+        let result = loop {
+            match queue.fetch_batch(5, Duration::from_millis(500)) {
+                Ok(batch) if batch.is_empty() => break Ok(fetched),
+                Ok(batch) => fetched.extend(batch.iter().map(|message| message.body().to_vec())),
+                Err(err) => break Err(err),
+            }
+        };
+        stop.store(true, Ordering::SeqCst);
+        result
+    })?;
+    assert_eq!(fetched, pushed);
+
+    drop((queue, other));
+    assert!(session.write().unwrap().close().is_clean());
+    println!("Batch concurrent test passed!");
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+use crate::mq::api::common::{ChannelApi, ChannelQueueApi};
+use crate::mq::error::MqError;
+use crate::mq::io::channel::{ChannelClosed, ChannelState};
+use crate::mq::io::config::SessionConfig;
+use crate::mq::io::factory::Routing;
+use crate::mq::io::session::Session;
+use crate::mq::io::transport::{MemoryListener, Transport};
+use crate::mq::routing::chain::RoutingChainFactory;
+use crate::test::stub_broker::stub_session;
+
+#[test]
+pub fn channel_test() -> Result<(), Box<dyn std::error::Error>> {
+    let config = SessionConfig::new().read_timeout(Duration::from_millis(200));
+    let (_broker, session) = stub_session(config.clone())?;
+    let chain = RoutingChainFactory::new()
+        .add_key(Routing::Route(String::from("base_exc")))
+        .add_key(Routing::Stop)
+        .set_queue_name(String::from("base_queue"))
+        .build();
+    let state = |name: &str| session.read().unwrap().channel_state(&name.to_string());
+    let closed_state = |err: MqError| match err {
+        MqError::ChannelClosed(ChannelClosed { state, .. }) => Some(state),
+        _ => None,
+    };
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    channel.write().unwrap().create_queue(String::from("base_queue"), chain.clone())?;
+    let queue = channel.write().unwrap().get_queue(chain.clone())?;
+    assert_eq!(channel.read().unwrap().state(), ChannelState::Open);
+    queue.push_string(String::from("while open"))?;
+
+    // after close() nothing goes out on the channel any more
+    channel.write().unwrap().close();
+    assert_eq!(channel.read().unwrap().state(), ChannelState::Closing);
+    assert_eq!(closed_state(queue.push_string(String::from("after close")).unwrap_err()), Some(ChannelState::Closing));
+    assert_eq!(closed_state(channel.write().unwrap().get_queue(chain.clone()).err().unwrap()), Some(ChannelState::Closing));
+    assert!(session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).is_none());
+
+    // the confirmation is picked up by whichever channel reads next
+    let other = session.write().unwrap().create_channel("MQ_CHANNEL_OTHER".to_string(), None).unwrap();
+    assert!(other.write().unwrap().read().is_err());
+    assert_eq!(state("MQ_CHANNEL"), Some(ChannelState::Closed));
+    let reopened = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None);
+    assert!(reopened.is_some());
+
+    // a queue keeps its channel open, dropping the last handle closes it
+    let dropped = session.write().unwrap().create_channel("MQ_CHANNEL_DROPPED".to_string(), None).unwrap();
+    let dropped_queue = dropped.write().unwrap().get_queue(chain.clone())?;
+    drop(dropped);
+    assert_eq!(state("MQ_CHANNEL_DROPPED"), Some(ChannelState::Open));
+    drop(dropped_queue);
+    assert_eq!(state("MQ_CHANNEL_DROPPED"), Some(ChannelState::Closing));
+    assert!(other.write().unwrap().read().is_err());
+    assert_eq!(state("MQ_CHANNEL_DROPPED"), Some(ChannelState::Closed));
+
+    // dropping a channel through the session keeps its name taken until the close is confirmed
+    let dropped = session.write().unwrap().create_channel("MQ_CHANNEL_SESSION".to_string(), None).unwrap();
+    session.write().unwrap().drop_channel("MQ_CHANNEL_SESSION".to_string());
+    assert_eq!(dropped.read().unwrap().state(), ChannelState::Closing);
+    assert!(session.write().unwrap().create_channel("MQ_CHANNEL_SESSION".to_string(), None).is_none());
+    assert!(other.write().unwrap().read().is_err());
+    assert_eq!(state("MQ_CHANNEL_SESSION"), Some(ChannelState::Closed));
+    assert!(session.write().unwrap().create_channel("MQ_CHANNEL_SESSION".to_string(), None).is_some());
+    assert!(session.write().unwrap().close().is_clean());
+
+    // without reconnects a lost connection fails every channel
+    let (listener, connector) = MemoryListener::bind();
+    let session = Session::open_with(Box::new(connector), config)?;
+    let server = listener.accept()?;
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = channel.write().unwrap().get_queue(chain)?;
+    server.shutdown()?;
+    assert!(channel.write().unwrap().read().is_err());
+    assert_eq!(channel.read().unwrap().state(), ChannelState::Failed);
+    assert_eq!(closed_state(queue.push_string(String::from("after failure")).unwrap_err()), Some(ChannelState::Failed));
+
+    println!("Channel lifecycle test passed!");
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::io::{IoSlice, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::mq::api::common::{ChannelQueueApi, FetchResultString};
+use crate::mq::io::session::Session;
+use crate::mq::io::transport::{Connector, MemoryListener, Transport};
+use crate::test::stub_broker::{stub_chain, stub_session, wait_for, StubBroker};
+
+// Keeps count of the live handles on its connections, the writer thread holds one until it exits.
+struct Counted {
+    inner: Box<dyn Transport>,
+    live: Arc<AtomicUsize>,
+}
+
+impl Counted {
+    fn wrap(inner: Box<dyn Transport>, live: &Arc<AtomicUsize>) -> Box<dyn Transport> {
+        live.fetch_add(1, Ordering::SeqCst);
+        Box::new(Counted { inner, live: live.clone() })
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.live.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Read for Counted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Transport for Counted {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn shutdown(&self) -> Result<(), std::io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn poll_ready(&mut self, len: usize) -> Result<bool, std::io::Error> {
+        self.inner.poll_ready(len)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        Ok(Counted::wrap(self.inner.try_clone()?, &self.live))
+    }
+}
+
+struct CountedConnector<C: Connector> {
+    inner: C,
+    live: Arc<AtomicUsize>,
+}
+
+impl<C: Connector> Connector for CountedConnector<C> {
+    fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        Ok(Counted::wrap(self.inner.connect()?, &self.live))
+    }
+}
+
+fn names(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+#[test]
+pub fn close_test() -> Result<(), Box<dyn std::error::Error>> {
+    // a broker that confirms every close leaves nothing to report
+    let (_broker, session) = stub_session(Default::default())?;
+    let first = session.write().unwrap().create_channel("MQ_FIRST".to_string(), None).unwrap();
+    let second = session.write().unwrap().create_channel("MQ_SECOND".to_string(), None).unwrap();
+    let report = session.write().unwrap().close_timeout(Duration::from_secs(2));
+    assert!(report.is_clean(), "unexpected report {report:?}");
+    assert!(first.read().unwrap().is_closed());
+    assert!(second.read().unwrap().is_closed());
+
+    // a broker that stops answering leaves every close unconfirmed once the deadline is up
+    let (broker, session) = stub_session(Default::default())?;
+    let _first = session.write().unwrap().create_channel("MQ_FIRST".to_string(), None).unwrap();
+    let _second = session.write().unwrap().create_channel("MQ_SECOND".to_string(), None).unwrap();
+    broker.silent.store(true, Ordering::SeqCst);
+    let started = Instant::now();
+    let report = session.write().unwrap().close_timeout(Duration::from_millis(200));
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(!report.is_clean());
+    assert_eq!(names(report.unconfirmed), vec!["MQ_FIRST", "MQ_SECOND"]);
+    assert!(report.failed.is_empty());
+
+    // a connection that is gone fails every close, nothing is left waiting
+    let (broker, session) = stub_session(Default::default())?;
+    let first = session.write().unwrap().create_channel("MQ_FIRST".to_string(), None).unwrap();
+    let _second = session.write().unwrap().create_channel("MQ_SECOND".to_string(), None).unwrap();
+    // a round trip first, so the broker has taken the connection before it is cut
+    assert!(matches!(first.write().unwrap().get_queue(stub_chain("closing"))?.fetch_simple_string(), FetchResultString::FailedNoItem));
+    broker.drop_connections();
+    let report = session.write().unwrap().close_timeout(Duration::from_secs(2));
+    assert!(!report.is_clean());
+    assert_eq!(names(report.failed.iter().map(|(name, _)| name.clone()).collect()), vec!["MQ_FIRST", "MQ_SECOND"]);
+    assert!(report.unconfirmed.is_empty());
+
+    // once closed and let go of, the session is dropped and its writer thread exits
+    let (listener, connector) = MemoryListener::bind();
+    let _broker = StubBroker::spawn(listener);
+    let live = Arc::new(AtomicUsize::new(0));
+    let session = Session::open_with(Box::new(CountedConnector { inner: connector, live: live.clone() }), Default::default())?;
+    let channel = session.write().unwrap().create_channel("MQ_FIRST".to_string(), None).unwrap();
+    assert!(live.load(Ordering::SeqCst) > 0);
+    assert!(session.write().unwrap().close_timeout(Duration::from_secs(2)).is_clean());
+    drop(channel);
+    drop(session);
+    wait_for(|| live.load(Ordering::SeqCst) == 0)?;
+
+    println!("Close test passed!");
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+use crate::mq::api::codec::{LengthPrefixedCodec, MessageCodec, RawCodec, Utf8Codec};
+use crate::mq::error::{MqError, MqResult};
+use crate::mq::io::ack::AckMode;
+use crate::test::stub_broker::{stub_queue, stub_session, wait_for};
+
+// a codec of our own: a reading as a little-endian u64
+#[derive(Clone)]
+struct ReadingCodec;
+
+impl MessageCodec<u64> for ReadingCodec {
+    fn encode(&self, value: &u64) -> MqResult<Vec<u8>> {
+        Ok(value.to_le_bytes().to_vec())
+    }
+
+    fn decode(&self, body: &[u8]) -> MqResult<u64> {
+        let bytes = <[u8; 8]>::try_from(body).map_err(|_| MqError::Codec(format!("a reading has 8 bytes, not {}", body.len())))?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[test]
+pub fn codec_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (broker, session) = stub_session(Default::default())?;
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = stub_queue(&channel, "typed")?;
+    let queued = || broker.queued("MQ_HOST/typed");
+
+    let strings = queue.typed(Utf8Codec);
+    strings.push(&String::from("grüße"))?;
+    assert_eq!(strings.fetch()?.as_deref(), Some("grüße"));
+    queue.push(vec![0xc3, 0x28])?;
+    assert!(matches!(strings.fetch(), Err(MqError::Codec(_))));
+    assert!(strings.fetch()?.is_none());
+
+    let raw = queue.typed(RawCodec);
+    raw.push(&vec![0, 1, 2, 0])?;
+    assert_eq!(raw.fetch()?, Some(vec![0, 1, 2, 0]));
+
+    let fields = queue.typed(LengthPrefixedCodec);
+    let record = vec![b"id".to_vec(), vec![], b"payload".to_vec()];
+    fields.push(&record)?;
+    let (decoded, message) = fields.fetch_message()?.ok_or("expected a record")?;
+    assert_eq!(decoded, record);
+    assert_eq!(message.len(), 4 * 3 + 9);
+    assert!(matches!(LengthPrefixedCodec.decode(&[5, 0, 0, 0, b'a']), Err(MqError::Codec(_))));
+    assert_eq!(LengthPrefixedCodec.decode(&[])?, Vec::<Vec<u8>>::new());
+
+    // with manual acks only messages are fetched, one that does not decode is rejected and the rest acked
+    channel.write().unwrap().set_ack_mode(AckMode::Manual);
+    let readings = queue.typed(ReadingCodec);
+    readings.push_batch(&[1, 2])?;
+    queue.push(b"oops".to_vec())?;
+    readings.push(&3)?;
+    assert!(matches!(readings.fetch_batch(4, Duration::from_secs(1)), Err(MqError::Validation(_))));
+    assert!(matches!(readings.fetch(), Err(MqError::Validation(_))));
+    assert!(matches!(queue.fetch_string(), Err(MqError::Validation(_))));
+    wait_for(|| queued() == 4)?;
+    let batch = readings.fetch_message_batch(4, Duration::from_secs(1))?;
+    assert_eq!(batch.len(), 4);
+    assert_eq!(batch.iter().filter_map(|value| value.as_ref().ok()).map(|(value, _)| *value).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert!(matches!(batch[2], Err(MqError::Codec(_))));
+    assert_eq!(channel.read().unwrap().unacked(), 3);
+    batch.iter().filter_map(|value| value.as_ref().ok()).try_for_each(|(_, message)| message.ack())?;
+    assert_eq!(channel.read().unwrap().unacked(), 0);
+    readings.push(&4)?;
+    let (value, message) = readings.fetch_message_timeout(Duration::from_secs(1))?.ok_or("expected a reading")?;
+    assert_eq!(value, 4);
+    message.ack()?;
+    assert_eq!(queued(), 0);
+
+    drop(batch);
+    drop((strings, raw, fields, readings, queue));
+    session.write().unwrap().close();
+    println!("Codec test passed!");
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+use crate::mq::error::MqError;
+use crate::mq::io::confirm::Confirmation;
+use crate::mq::io::config::SessionConfig;
+use crate::mq::io::reconnect::ReconnectPolicy;
+use crate::test::stub_broker::{stub_queue, stub_session};
+
+#[test]
+pub fn confirm_test() -> Result<(), Box<dyn std::error::Error>> {
+    let config = SessionConfig::new()
+        .reconnect_policy(Some(ReconnectPolicy::new().initial_delay(Duration::from_millis(10))));
+    let (broker, session) = stub_session(config)?;
+    let timeout = Duration::from_secs(2);
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = stub_queue(&channel, "orders")?;
+    let queued = || broker.queued("MQ_HOST/orders");
+
+    // outside of confirm mode there is nothing to wait for
+    assert!(matches!(queue.push_confirmed(b"early".to_vec(), timeout), Err(MqError::Validation(_))));
+    queue.push_string(String::from("early"))?;
+    assert_eq!(queue.fetch_string()?.as_deref(), Some("early"));
+
+    channel.write().unwrap().enable_confirms();
+    assert!(channel.read().unwrap().confirms_enabled());
+    queue.push_confirmed(b"first".to_vec(), timeout)?;
+    assert_eq!(queued(), 1);
+
+    // a confirm arriving ahead of a fetch reply does not get in its way
+    let seq = queue.push_pending(b"second".to_vec())?;
+    assert_eq!(queue.fetch_string()?.as_deref(), Some("first"));
+    assert_eq!(queue.confirms().wait(seq, timeout)?, Confirmation::Acked);
+
+    // many publishes in flight, settled together
+    let confirms = channel.read().unwrap().confirms();
+    let seqs = (0..100).map(|i| queue.push_pending(format!("bulk {i}").into_bytes())).collect::<Result<Vec<_>, _>>()?;
+    let settled = confirms.wait_all(timeout)?;
+    assert_eq!(settled.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), seqs);
+    assert!(settled.iter().all(|(_, confirmation)| *confirmation == Confirmation::Acked));
+    assert_eq!(confirms.outstanding(), 0);
+    assert_eq!(queued(), 101);
+
+    // plain pushes and batches ask for no confirm, there is nothing left behind for them
+    queue.push(b"plain".to_vec())?;
+    queue.push_batch(vec![b"one".to_vec(), b"two".to_vec()])?;
+    assert_eq!(confirms.outstanding(), 0);
+    queue.push_confirmed(b"confirmed".to_vec(), timeout)?;
+    assert!(confirms.drain().is_empty());
+
+    // the broker refuses the message
+    broker.refused.lock().unwrap().insert(String::from("MQ_HOST/orders"));
+    match queue.push_confirmed(b"refused".to_vec(), timeout) {
+        Err(MqError::Unconfirmed { confirmation: Confirmation::Nacked, .. }) => {}
+        other => return Err(format!("expected a nack, got {other:?}").into()),
+    }
+    assert_eq!(queued(), 105);
+    broker.refused.lock().unwrap().clear();
+
+    // confirms still in flight when the connection goes are lost
+    let seq = queue.push_pending(b"in flight".to_vec())?;
+    session.read().unwrap().reconnect()?;
+    assert_eq!(confirms.drain(), vec![(seq, Confirmation::Lost)]);
+    queue.push_confirmed(b"after reconnect".to_vec(), timeout)?;
+
+    drop(queue);
+    session.write().unwrap().close();
+    println!("Confirm test passed!");
+    Ok(())
+}
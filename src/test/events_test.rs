@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::mq::io::cache::{CacheLimit, OverflowPolicy};
+use crate::mq::io::channel::ChannelState;
+use crate::mq::io::config::SessionConfig;
+use crate::mq::io::events::{DropReason, EventBus, SessionEvent};
+use crate::mq::io::factory::{DataType, MessageFactory, MessageType, RoutingModFactory};
+use crate::mq::io::reconnect::ReconnectPolicy;
+use crate::test::stub_broker::{stub_chain, stub_session};
+
+#[test]
+pub fn events_test() -> Result<(), Box<dyn std::error::Error>> {
+    let bus = EventBus::new();
+    let events = bus.channel();
+    let opened = Arc::new(AtomicUsize::new(0));
+    let counter = opened.clone();
+    bus.subscribe(move |event| {
+        if let SessionEvent::ChannelOpened { .. } = event {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    let config = SessionConfig::new()
+        .read_timeout(Duration::from_millis(200))
+        .reconnect_policy(Some(ReconnectPolicy::new().max_attempts(Some(3))))
+        .events(bus);
+    let (_broker, session) = stub_session(config)?;
+    assert_eq!(events.try_recv()?, SessionEvent::Connected);
+
+    let chain = stub_chain("base_queue");
+    let fetch = |channel: &str| MessageFactory::new("MQ_HOST".to_string(), channel.to_string())
+        .routing_mod(RoutingModFactory::new().data_type(DataType::Message).message_type(MessageType::Fetch).build())
+        .routing_chain(chain.clone())
+        .build();
+
+    let reader = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let bounded = session.write().unwrap().create_channel("MQ_CHANNEL_BOUNDED".to_string(), None).unwrap();
+    session.read().unwrap().set_channel_cache_limit(&"MQ_CHANNEL_BOUNDED".to_string(), CacheLimit::new(1, OverflowPolicy::DropNewest));
+    assert_eq!(opened.load(Ordering::SeqCst), 2);
+
+    // replies for a channel nobody opened, and more replies than the bounded cache holds
+    session.read().unwrap().send(fetch("MQ_GHOST"))?;
+    bounded.write().unwrap().send(fetch("MQ_CHANNEL_BOUNDED"))?;
+    bounded.write().unwrap().send(fetch("MQ_CHANNEL_BOUNDED"))?;
+    for _ in 0..3 {
+        assert!(reader.write().unwrap().read().is_err());
+    }
+    bounded.write().unwrap().close();
+    assert!(reader.write().unwrap().read().is_err());
+
+    session.read().unwrap().reconnect()?;
+    session.write().unwrap().close();
+
+    let seen = events.try_iter().collect::<Vec<_>>();
+    let expected = [
+        SessionEvent::ChannelOpened { host: "MQ_HOST".to_string(), channel: "MQ_CHANNEL".to_string() },
+        SessionEvent::UnknownChannel { channel: "MQ_GHOST".to_string() },
+        SessionEvent::CacheOverflow { channel: "MQ_CHANNEL_BOUNDED".to_string(), policy: OverflowPolicy::DropNewest },
+        SessionEvent::FrameDropped { channel: "MQ_CHANNEL_BOUNDED".to_string(), reason: DropReason::CacheFull },
+        SessionEvent::ChannelClosed { channel: "MQ_CHANNEL_BOUNDED".to_string(), state: ChannelState::Closed },
+        SessionEvent::Disconnected { reason: "reconnect requested".to_string(), reconnecting: true },
+        SessionEvent::Reconnected { attempts: 1 },
+        SessionEvent::ChannelClosed { channel: "MQ_CHANNEL".to_string(), state: ChannelState::Closed },
+        SessionEvent::Disconnected { reason: "session closed".to_string(), reconnecting: false },
+    ];
+    // in this order, other events may come in between
+    let mut rest = seen.iter();
+    for event in expected.iter() {
+        if !rest.any(|seen| seen == event) {
+            return Err(format!("missing {event:?} in {seen:?}").into());
+        }
+    }
+
+    println!("Session events test passed!");
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+use crate::mq::api::common::{ChannelApi, ChannelQueueApi};
+use crate::mq::io::config::SessionConfig;
+use crate::mq::io::factory::Routing;
+use crate::mq::io::failover::{FailoverConnector, FailoverOrder};
+use crate::mq::io::reconnect::ReconnectPolicy;
+use crate::mq::io::session::Session;
+use crate::mq::io::transport::{Connector, MemoryListener};
+use crate::mq::routing::chain::RoutingChainFactory;
+use crate::test::stub_broker::StubBroker;
+
+#[test]
+pub fn failover_test() -> Result<(), Box<dyn std::error::Error>> {
+    let config = SessionConfig::from_url("kyuu://a,b:4000, c:11452/MQ_HOST?failover=round_robin")?;
+    assert_eq!(config.endpoints, vec!["a:11451", "b:4000", "c:11452"]);
+    assert_eq!(config.failover, FailoverOrder::RoundRobin);
+    assert!(SessionConfig::from_url("kyuu://a,,b/MQ_HOST").is_err());
+
+    // nothing listens on port 1, this used to panic
+    assert!(Session::new("127.0.0.1:1", "MQ_HOST".to_string()).is_err());
+    let refused = SessionConfig::new().endpoints(vec!["127.0.0.1:1".to_string(), "127.0.0.1:2".to_string()]);
+    assert!(Session::open(refused).is_err());
+
+    let (listener_a, connector_a) = MemoryListener::bind();
+    let (listener_b, connector_b) = MemoryListener::bind();
+    let (listener_c, connector_c) = MemoryListener::bind();
+    let _brokers = [listener_a, listener_b, listener_c].map(StubBroker::spawn);
+    let round_robin = FailoverConnector::new(vec![Box::new(connector_a), Box::new(connector_b), Box::new(connector_c)], FailoverOrder::RoundRobin);
+    let mut picked = vec![];
+    for _ in 0..4 {
+        round_robin.connect()?;
+        picked.push(round_robin.current().unwrap());
+    }
+    assert_eq!(picked, vec![0, 1, 2, 0]);
+
+    // the first endpoint is down, the session settles on the second and moves to the third
+    // once the connection to the second is declared lost
+    let (dead, down) = MemoryListener::bind();
+    drop(dead);
+    let (listener_a, connector_a) = MemoryListener::bind();
+    let (listener_b, connector_b) = MemoryListener::bind();
+    let broker_a = StubBroker::spawn(listener_a);
+    let broker_b = StubBroker::spawn(listener_b);
+    let connector = FailoverConnector::new(vec![Box::new(down), Box::new(connector_a), Box::new(connector_b)], FailoverOrder::InOrder);
+    let config = SessionConfig::new()
+        .read_timeout(Duration::from_millis(300))
+        .reconnect_policy(Some(ReconnectPolicy::new().initial_delay(Duration::from_millis(10)).max_attempts(Some(3))));
+    let session = Session::open_with(Box::new(connector), config)?;
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let chain = RoutingChainFactory::new()
+        .add_key(Routing::Route(String::from("base_exc")))
+        .add_key(Routing::Stop)
+        .set_queue_name(String::from("base_queue"))
+        .build();
+    channel.write().unwrap().create_queue(String::from("base_queue"), chain.clone())?;
+    let queue = channel.write().unwrap().get_queue(chain)?;
+
+    queue.push_string(String::from("to a"))?;
+    session.read().unwrap().reconnect()?;
+    queue.push_string(String::from("to b"))?;
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!((broker_a.queued("MQ_HOST/base_queue"), broker_b.queued("MQ_HOST/base_queue")), (1, 1));
+
+    assert!(session.write().unwrap().close().is_clean());
+    println!("Failover test passed!");
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::mq::io::config::SessionConfig;
+use crate::test::stub_broker::{stub_queue, stub_session};
+
+#[test]
+pub fn fetch_timeout_test() -> Result<(), Box<dyn std::error::Error>> {
+    for long_poll in [false, true] {
+        let config = SessionConfig::new()
+            .long_poll(long_poll)
+            .read_timeout(Duration::from_millis(200));
+        let (broker, session) = stub_session(config)?;
+        let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+        let queue = stub_queue(&channel, "jobs")?;
+
+        // an empty queue is waited on for the whole timeout
+        let started = Instant::now();
+        assert!(queue.fetch_timeout(Duration::from_millis(300))?.is_none());
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_millis(300) && waited < Duration::from_millis(800));
+
+        // and a message arriving in the meantime is handed out as soon as it is there
+        let queues = broker.queues.clone();
+        let producer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            queues.lock().unwrap().entry("MQ_HOST/jobs".to_string()).or_default().push_back(b"late".to_vec());
+        });
+        let started = Instant::now();
+        let message = queue.fetch_timeout(Duration::from_secs(2))?.ok_or("expected a message")?;
+        assert_eq!(message.body(), b"late");
+        assert!(started.elapsed() < Duration::from_secs(1));
+        producer.join().unwrap();
+
+        // long polling asks the broker once per call, polling backs off but still asks repeatedly
+        let fetches = broker.fetches.load(Ordering::SeqCst);
+        if long_poll {
+            assert_eq!(fetches, 2);
+        } else {
+            assert!(fetches > 2 && fetches < 30);
+        }
+
+        drop(queue);
+        assert!(session.write().unwrap().close().is_clean());
+    }
+    println!("Fetch timeout test passed!");
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::mq::api::common::FetchResultString;
+use crate::mq::error::MqError;
+use crate::test::stub_broker::{stub_queue, stub_session};
+
+#[test]
+pub fn heartbeat_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (broker, session) = stub_session(Default::default())?;
+
+    // the shorter of the two intervals wins, a broker without an opinion takes the client's
+    assert_eq!(session.write().unwrap().enable_heartbeat(Duration::from_millis(500), 2)?, Duration::from_millis(500));
+    *broker.heartbeat.lock().unwrap() = Some(Duration::from_millis(40));
+    assert_eq!(session.write().unwrap().enable_heartbeat(Duration::from_millis(500), 2)?, Duration::from_millis(40));
+    assert_eq!(session.write().unwrap().enable_heartbeat(Duration::from_millis(30), 2)?, Duration::from_millis(30));
+    assert_eq!(session.write().unwrap().enable_heartbeat(Duration::from_millis(40), 2)?, Duration::from_millis(40));
+    assert_eq!(session.read().unwrap().heartbeat_interval(), Some(Duration::from_millis(40)));
+    assert!(matches!(session.read().unwrap().enable_heartbeat(Duration::ZERO, 2), Err(MqError::Validation(_))));
+
+    // turned off and straight back on, the old thread makes way for a new one
+    session.read().unwrap().disable_heartbeat();
+    assert_eq!(session.read().unwrap().enable_heartbeat(Duration::from_millis(40), 2)?, Duration::from_millis(40));
+    assert_eq!(session.read().unwrap().heartbeat_thread(), 2);
+
+    // an idle session that hears back from the broker stays up well past max_missed intervals
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = stub_queue(&channel, "beats")?;
+    thread::sleep(Duration::from_millis(300));
+    queue.push_string(String::from("alive"))?;
+    assert!(matches!(queue.fetch_simple_string(), FetchResultString::Success(s) if s == "alive"));
+
+    // a broker that stops answering is given up on after two missed heartbeats
+    broker.silent.store(true, Ordering::SeqCst);
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !session.read().unwrap().is_disconnected() {
+        assert!(Instant::now() < deadline, "the session never noticed the missed heartbeats");
+        thread::sleep(Duration::from_millis(10));
+    }
+    match session.write().unwrap().send(vec![0u8; 256]) {
+        Err(MqError::Disconnected(reason)) => assert!(reason.starts_with("missed "), "unexpected reason {reason:?}"),
+        other => panic!("expected a disconnect, got {other:?}"),
+    }
+
+    println!("Heartbeat test passed!");
+    Ok(())
+}
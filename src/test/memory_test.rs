@@ -0,0 +1,79 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::mq::api::common::{ChannelApi, ChannelQueueApi, FetchResultString};
+use crate::mq::io::cache::{CacheLimit, OverflowPolicy};
+use crate::mq::io::factory::{DataType, MessageType, Routing, RoutingModFactory};
+use crate::mq::routing::chain::RoutingChainFactory;
+use crate::test::stub_broker::stub_session;
+
+#[test]
+pub fn memory_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (_broker, session) = stub_session(Default::default())?;
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let chain = RoutingChainFactory::new()
+        .add_key(Routing::Route(String::from("base_exc")))
+        .add_key(Routing::Stop)
+        .set_queue_name(String::from("base_queue"))
+        .build();
+    channel.write().unwrap().create_exchange(String::from("base_exc"), chain.clone())?;
+    channel.write().unwrap().create_queue(String::from("base_queue"), chain.clone())?;
+
+    let queue_chain = chain.clone();
+    let queue = channel.write().unwrap().get_queue(chain)?;
+    for i in 0..3 {
+        queue.push_string(format!("hello from memory: {i}"))?;
+    }
+    for i in 0..3 {
+        match queue.fetch_simple_string() {
+            FetchResultString::Success(s) => assert_eq!(s, format!("hello from memory: {i}")),
+            _ => return Err("expected a message".into()),
+        }
+    }
+    if !matches!(queue.fetch_simple_string(), FetchResultString::FailedNoItem) {
+        return Err("expected an empty queue".into());
+    }
+
+    // a reader parked on an idle stream must not hold up producers on the same session
+    let idle = session.write().unwrap().create_channel("MQ_CHANNEL_IDLE".to_string(), None).unwrap();
+    let reader = thread::spawn(move || idle.write().unwrap().read().is_err());
+    thread::sleep(Duration::from_millis(50));
+    let start = Instant::now();
+    for i in 0..10 {
+        queue.push_string(format!("pushed past a blocked reader: {i}"))?;
+    }
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert!(reader.join().unwrap());
+    while let FetchResultString::Success(_) = queue.fetch_simple_string() {}
+
+    // frames for a channel that does not read pile up in its cache until the limit kicks in
+    let bounded = session.write().unwrap().create_channel("MQ_CHANNEL_BOUNDED".to_string(), None).unwrap();
+    session.read().unwrap().set_channel_cache_limit(&"MQ_CHANNEL_BOUNDED".to_string(), CacheLimit::new(2, OverflowPolicy::DropOldest));
+    let bounded_queue = bounded.write().unwrap().get_queue(queue_chain.clone())?;
+    for i in 0..3 {
+        bounded_queue.push_string(format!("bounded: {i}"))?;
+    }
+    for _ in 0..3 {
+        let fetch = bounded.read().unwrap().get_factory()
+            .routing_mod(RoutingModFactory::new()
+                .data_type(DataType::Message)
+                .message_type(MessageType::Fetch)
+                .build()
+            )
+            .routing_chain(queue_chain.clone())
+            .build();
+        bounded.write().unwrap().send(fetch)?;
+    }
+    let other = session.write().unwrap().create_channel("MQ_CHANNEL_OTHER".to_string(), None).unwrap();
+    // one more frame: the broker confirming the close of the idle channel, dropped by its reader thread
+    for _ in 0..4 {
+        assert!(other.write().unwrap().read().is_err());
+    }
+    let stats = session.read().unwrap().cache_stats(&"MQ_CHANNEL_BOUNDED".to_string()).unwrap();
+    assert_eq!((stats.len, stats.dropped_oldest), (2, 1));
+
+    let report = session.write().unwrap().close();
+    assert!(report.is_clean());
+    println!("Memory transport test passed!");
+    Ok(())
+}
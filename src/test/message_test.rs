@@ -0,0 +1,56 @@
+use crate::mq::api::common::{ChannelApi, ChannelQueueApi, FetchResult};
+use crate::mq::api::message::Message;
+use crate::mq::error::MqError;
+use crate::mq::io::factory::{DataType, MessageFactory, MessageType, Routing, RoutingModFactory, RoutingType};
+use crate::mq::routing::chain::RoutingChainFactory;
+use crate::test::stub_broker::stub_session;
+
+#[test]
+pub fn message_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (_broker, session) = stub_session(Default::default())?;
+
+    let chain = RoutingChainFactory::new()
+        .add_key(Routing::Route(String::from("orders_exc")))
+        .add_key(Routing::Stop)
+        .set_queue_name(String::from("orders"))
+        .build();
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    channel.write().unwrap().create_queue(String::from("orders"), chain.clone())?;
+    let queue = channel.write().unwrap().get_queue(chain.clone())?;
+
+    // the body comes back exactly as pushed, without the padding of the wire format
+    queue.push(b"hello".to_vec())?;
+    let message = queue.fetch()?.ok_or("expected a message")?;
+    assert_eq!(message.body(), b"hello");
+    assert_eq!(message.virtual_host, "MQ_HOST");
+    assert_eq!(message.channel, "MQ_CHANNEL");
+    assert_eq!(message.queue_name(), "orders");
+    assert_eq!(message.routing.routing_key[0], Routing::Route(String::from("orders_exc")));
+    assert_eq!(message.routing_type, RoutingType::Direct);
+    assert_eq!(message.error_code(), None);
+
+    assert!(queue.fetch()?.is_none());
+    assert!(matches!(queue.fetch_simple(), FetchResult::FailedNoItem));
+    queue.push(vec![0xff, 0xfe])?;
+    assert!(matches!(queue.fetch_string(), Err(MqError::Codec(_))));
+
+    // a reply read by another channel is cached together with its head
+    queue.push_string(String::from("cached"))?;
+    let other = session.write().unwrap().create_channel("MQ_OTHER".to_string(), None).unwrap();
+    other.write().unwrap().send(
+        MessageFactory::new("MQ_HOST".to_string(), "MQ_OTHER".to_string())
+            .routing_mod(RoutingModFactory::new().data_type(DataType::Message).message_type(MessageType::Fetch).build())
+            .routing_chain(chain.clone())
+            .build()
+    )?;
+    assert!(matches!(channel.write().unwrap().read(), Err(MqError::Timeout)));
+    let (head, payload) = other.write().unwrap().read()?;
+    let cached = Message::from_frame(head, payload);
+    assert_eq!(cached.channel, "MQ_OTHER");
+    assert_eq!(cached.body_string()?, "cached");
+
+    drop(queue);
+    assert!(session.write().unwrap().close().is_clean());
+    println!("Message test passed!");
+    Ok(())
+}
@@ -1,2 +1,50 @@
 pub mod mpsc_test;
-pub mod spmc_test;
\ No newline at end of file
+pub mod spmc_test;
+#[cfg(test)]
+pub mod stub_broker;
+#[cfg(test)]
+pub mod reconnect_test;
+#[cfg(test)]
+pub mod heartbeat_test;
+#[cfg(test)]
+pub mod close_test;
+#[cfg(test)]
+pub mod memory_test;
+#[cfg(test)]
+pub mod writer_test;
+#[cfg(test)]
+pub mod pool_test;
+#[cfg(test)]
+pub mod config_test;
+#[cfg(test)]
+pub mod failover_test;
+#[cfg(test)]
+pub mod auth_test;
+#[cfg(test)]
+pub mod vhost_test;
+#[cfg(test)]
+pub mod channel_test;
+#[cfg(test)]
+pub mod events_test;
+#[cfg(test)]
+pub mod message_test;
+#[cfg(test)]
+pub mod subscription_test;
+#[cfg(test)]
+pub mod fetch_timeout_test;
+#[cfg(test)]
+pub mod adapter_test;
+#[cfg(test)]
+pub mod batch_test;
+#[cfg(test)]
+pub mod ack_test;
+#[cfg(test)]
+pub mod confirm_test;
+#[cfg(test)]
+pub mod prefetch_test;
+#[cfg(test)]
+pub mod rpc_test;
+#[cfg(test)]
+pub mod codec_test;
+#[cfg(test)]
+pub mod transport_test;
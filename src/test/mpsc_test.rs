@@ -4,21 +4,21 @@ use crate::mq::io::factory::{CommandType, DataType, MessageType, Routing, Routin
 use crate::mq::io::session;
 
 pub fn mpsc_test() -> Result<(), Box<dyn std::error::Error>> {
-    let mut session = session::Session::new("127.0.0.1:11451", "MQ_HOST".to_string());
+    let mut session = session::Session::new("127.0.0.1:11451", "MQ_HOST".to_string())?;
     let mut session = Arc::from(RwLock::from(session));
     session
         .clone()
         .write()
         .unwrap()
-        .init(session.clone());
+        .init(session.clone())?;
 
     println!("Conn established!");
-    session.write().unwrap().set_read_timeout(std::time::Duration::from_millis(500));
-    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string()).clone().unwrap();
-    let channel2 = session.write().unwrap().create_channel("MQ_CHANNEL2".to_string()).clone().unwrap();
+    session.write().unwrap().set_read_timeout(std::time::Duration::from_millis(500))?;
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).clone().unwrap();
+    let channel2 = session.write().unwrap().create_channel("MQ_CHANNEL2".to_string(), None).clone().unwrap();
     let channel2_cloned = channel2.clone();
     let channel_cloned = channel.clone();
-    let channel_r  = session.write().unwrap().create_channel("MQ_CHANNEL_R".to_string()).clone().unwrap();
+    let channel_r  = session.write().unwrap().create_channel("MQ_CHANNEL_R".to_string(), None).clone().unwrap();
 
     let msg = channel.read().unwrap().get_factory()
         .routing_mod(RoutingModFactory::new()
@@ -105,7 +105,7 @@ pub fn mpsc_test() -> Result<(), Box<dyn std::error::Error>> {
                 .queue_name(String::from("base_queue"))
                 .build();
             if let Ok((_, data)) = channel_r.write().unwrap().send_and_read(msg) {
-                println!("Fetched from channel read: {:?}", String::from_utf8(*data).unwrap().trim_end_matches("\0"));
+                println!("Fetched from channel read: {:?}", String::from_utf8(data.to_vec()).unwrap().trim_end_matches("\0"));
             } else {
                 println!("Failed to fetch from channel read!");
             }
@@ -0,0 +1,75 @@
+use crate::mq::api::common::FetchResultString;
+use crate::mq::io::pool::{BufferPool, Payload};
+use crate::test::stub_broker::{stub_queue, stub_session};
+
+#[test]
+pub fn pool_test() -> Result<(), Box<dyn std::error::Error>> {
+    // a buffer comes back once the last payload pointing into it is gone, and is handed out again zeroed
+    let pool = BufferPool::new(2, 1024);
+    let mut buf = pool.take(100);
+    buf.fill(0xff);
+    let address = buf.as_ptr();
+    let payload = pool.payload(buf);
+    let tail = payload.slice(50..100);
+    drop(payload);
+    assert_eq!(pool.pooled(), 0);
+    drop(tail);
+    assert_eq!(pool.pooled(), 1);
+    let buf = pool.take(80);
+    assert_eq!(buf.as_ptr(), address);
+    assert_eq!(buf, vec![0u8; 80]);
+    assert_eq!(pool.pooled(), 0);
+
+    // a buffer that is too small is left in the pool, one that grew too large is not taken back
+    let small = pool.payload(buf);
+    drop(small);
+    let large = pool.take(2048);
+    assert_eq!(pool.pooled(), 1);
+    drop(pool.payload(large));
+    assert_eq!(pool.pooled(), 1);
+
+    // the pool keeps at most max_buffers
+    let taken = (0..4).map(|_| pool.take(10)).collect::<Vec<_>>();
+    taken.into_iter().for_each(|buf| drop(pool.payload(buf)));
+    assert_eq!(pool.pooled(), 2);
+
+    // the smallest buffer that fits is taken, an empty one is never taken from the pool
+    let pool = BufferPool::new(4, 4096);
+    let taken = [1000, 100, 500].map(|len| pool.take(len));
+    taken.into_iter().for_each(|buf| drop(pool.payload(buf)));
+    assert_eq!(pool.pooled(), 3);
+    assert_eq!(pool.take(80).capacity(), 100);
+    assert_eq!(pool.take(0).capacity(), 0);
+    assert_eq!(pool.take(200).capacity(), 500);
+    assert_eq!(pool.pooled(), 1);
+
+    // slices count from the start of the slice they were taken from, and share the buffer
+    let payload = Payload::from_vec((0..10).collect());
+    let middle = payload.slice(2..8);
+    assert_eq!(middle.as_slice(), &[2, 3, 4, 5, 6, 7]);
+    let inner = middle.slice(1..3);
+    assert_eq!(inner.to_vec(), vec![3, 4]);
+    assert!(middle.slice(6..6).is_empty());
+    assert_eq!(inner, Payload::from_vec(vec![3, 4]));
+    assert!(std::panic::catch_unwind(|| middle.slice(4..7)).is_err());
+    let (start, end) = (5, 4);
+    assert!(std::panic::catch_unwind(|| middle.slice(start..end)).is_err());
+
+    // the session reads every reply into a buffer from its pool and keeps reusing it
+    let (_broker, session) = stub_session(Default::default())?;
+    let pool = BufferPool::new(8, 64 * 1024);
+    session.write().unwrap().set_buffer_pool(pool.clone());
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = stub_queue(&channel, "pooled")?;
+    for i in 0..3 {
+        queue.push_string(format!("body {i}"))?;
+    }
+    for i in 0..3 {
+        assert!(matches!(queue.fetch_simple_string(), FetchResultString::Success(s) if s == format!("body {i}")));
+    }
+    assert_eq!(pool.pooled(), 1);
+
+    session.write().unwrap().close();
+    println!("Pool test passed!");
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::thread;
+use std::time::Duration;
+use crate::mq::api::common::ChannelQueueApi;
+use crate::mq::io::ack::AckMode;
+use crate::test::stub_broker::{stub_chain, stub_queue, stub_session, wait_for};
+
+#[test]
+pub fn prefetch_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (broker, session) = stub_session(Default::default())?;
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queued = |name: &str| broker.queued(&format!("MQ_HOST/{name}"));
+
+    // the window is filled ahead of the consumer and topped up as it takes messages
+    channel.write().unwrap().set_prefetch(5);
+    let queue = stub_queue(&channel, "jobs")?;
+    for i in 0..20 {
+        queue.push_string(format!("job {i}"))?;
+    }
+    assert_eq!(queue.fetch_string()?.as_deref(), Some("job 0"));
+    wait_for(|| queue.buffered() == 5 && queued("jobs") == 14)?;
+    for i in 1..20 {
+        assert_eq!(queue.fetch()?.ok_or("expected a job")?.body_string()?, format!("job {i}"));
+    }
+    assert!(queue.fetch()?.is_none());
+    assert_eq!(queue.fetch_batch(3, Duration::from_millis(50))?.len(), 0);
+
+    drop(queue);
+
+    // with manual acks the window holds until messages are settled
+    let channel = session.write().unwrap().create_channel("MQ_MANUAL".to_string(), None).unwrap();
+    channel.write().unwrap().set_ack_mode(AckMode::Manual);
+    channel.write().unwrap().set_prefetch(3);
+    let queued = || queued("tasks");
+    let queue = stub_queue(&channel, "tasks")?;
+    for i in 0..10 {
+        queue.push_string(format!("task {i}"))?;
+    }
+    let taken = queue.fetch_batch(3, Duration::from_secs(1))?;
+    assert_eq!(taken.len(), 3);
+    wait_for(|| queued() == 7)?;
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(queue.buffered(), 0);
+    assert_eq!(queued(), 7);
+    taken[0].ack()?;
+    wait_for(|| queue.buffered() == 1 && queued() == 6)?;
+    taken[1].ack()?;
+    taken[2].ack()?;
+    wait_for(|| queue.buffered() == 3 && queued() == 4)?;
+
+    // what is still buffered goes back to the broker with the queue
+    drop(queue);
+    wait_for(|| queued() == 7)?;
+    assert_eq!(channel.read().unwrap().unacked(), 0);
+
+    // without a window every fetch goes to the broker again
+    channel.write().unwrap().set_prefetch(0);
+    let queue = channel.write().unwrap().get_queue(stub_chain("tasks"))?;
+    let message = queue.fetch()?.ok_or("expected task 3")?;
+    assert_eq!(message.body_string()?, "task 3");
+    message.ack()?;
+    assert_eq!(queue.buffered(), 0);
+    assert_eq!(queued(), 6);
+
+    drop(queue);
+    session.write().unwrap().close();
+    println!("Prefetch test passed!");
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use crate::mq::api::common::FetchResultString;
+use crate::mq::io::reconnect::ReconnectPolicy;
+use crate::mq::io::session::Session;
+use crate::mq::io::transport::{Connector, MemoryConnector, MemoryListener, Transport};
+use crate::test::stub_broker::{stub_queue, StubBroker};
+
+// refuses the next `refuse` connects, and remembers when each connect was tried
+struct FlakyConnector {
+    inner: MemoryConnector,
+    refuse: Arc<AtomicU32>,
+    attempts: Arc<Mutex<Vec<Instant>>>,
+}
+
+impl Connector for FlakyConnector {
+    fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        self.attempts.lock().unwrap().push(Instant::now());
+        if self.refuse.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |refuse| refuse.checked_sub(1)).is_ok() {
+            return Err(std::io::ErrorKind::ConnectionRefused.into());
+        }
+        self.inner.connect()
+    }
+}
+
+#[test]
+pub fn reconnect_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (listener, connector) = MemoryListener::bind();
+    let broker = StubBroker::spawn(listener);
+    let refuse = Arc::new(AtomicU32::new(0));
+    let attempts = Arc::new(Mutex::new(vec![]));
+    let connector = FlakyConnector {
+        inner: connector,
+        refuse: refuse.clone(),
+        attempts: attempts.clone(),
+    };
+    let policy = ReconnectPolicy::new()
+        .initial_delay(Duration::from_millis(20))
+        .multiplier(2.0)
+        .jitter(0.0)
+        .max_attempts(Some(4));
+    let session = Session::with_connector(Box::new(connector), "MQ_HOST".to_string())?;
+    let session = Arc::from(RwLock::from(session));
+    session.clone().write().unwrap().init(session.clone())?;
+    session.write().unwrap().set_reconnect_policy(Some(policy));
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = stub_queue(&channel, "jobs")?;
+    queue.push_string(String::from("before"))?;
+    assert!(matches!(queue.fetch_simple_string(), FetchResultString::Success(s) if s == "before"));
+    assert_eq!(*broker.declared.lock().unwrap(), vec!["MQ_HOST/jobs"]);
+
+    // the connection drops and the broker takes two attempts to come back, waiting longer each time
+    refuse.store(2, Ordering::SeqCst);
+    broker.drop_connections();
+    queue.push_string(String::from("after"))?;
+    let tried = attempts.lock().unwrap().clone();
+    assert_eq!(tried.len(), 1 + 3);
+    assert!(tried[2] - tried[1] >= Duration::from_millis(20));
+    assert!(tried[3] - tried[2] >= Duration::from_millis(40));
+
+    // the queue was declared again on the new connection
+    assert!(matches!(queue.fetch_simple_string(), FetchResultString::Success(s) if s == "after"));
+    assert_eq!(*broker.declared.lock().unwrap(), vec!["MQ_HOST/jobs", "MQ_HOST/jobs"]);
+
+    // once the attempts are used up the session gives up
+    refuse.store(u32::MAX, Ordering::SeqCst);
+    broker.drop_connections();
+    assert!(queue.push_string(String::from("lost")).is_err());
+    assert_eq!(attempts.lock().unwrap().len(), 4 + 4);
+
+    println!("Reconnect test passed!");
+    Ok(())
+}
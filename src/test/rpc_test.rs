@@ -0,0 +1,82 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::mq::api::rpc::{RpcClient, RpcServer};
+use crate::mq::error::MqError;
+use crate::test::stub_broker::{stub_chain, stub_queue, stub_session};
+
+#[test]
+pub fn rpc_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (_broker, session) = stub_session(Default::default())?;
+    let timeout = Duration::from_secs(2);
+
+    let server_channel = session.write().unwrap().create_channel("MQ_SERVER".to_string(), None).unwrap();
+    let requests = stub_queue(&server_channel, "rpc.upper")?;
+    let serve = |requests| RpcServer::serve(requests, |request| {
+        match request.body_string()?.as_str() {
+            "fail" => Err("cannot shout that".into()),
+            body => Ok(body.to_uppercase().into_bytes()),
+        }
+    });
+    let mut server = serve(requests.clone());
+
+    let client_channel = session.write().unwrap().create_channel("MQ_CLIENT".to_string(), None).unwrap();
+    let client = RpcClient::new(client_channel.clone(), stub_chain("rpc.upper"))?;
+    assert_eq!(client.call(b"hello".to_vec(), timeout)?.body_string()?, "HELLO");
+
+    // the handler's error comes back to the caller
+    match client.call(b"fail".to_vec(), timeout) {
+        Err(MqError::Rpc(reason)) => assert_eq!(reason, "cannot shout that"),
+        other => return Err(format!("expected the handler's error, got {other:?}").into()),
+    }
+
+    // calls from several threads each get their own reply
+    thread::scope(|scope| {
+        let calls = (0..8).map(|i| {
+            let client = &client;
+            scope.spawn(move || client.call(format!("call {i}").into_bytes(), timeout).and_then(|reply| reply.body_string()))
+        }).collect::<Vec<_>>();
+        for (i, call) in calls.into_iter().enumerate() {
+            assert_eq!(call.join().unwrap().unwrap(), format!("CALL {i}"));
+        }
+    });
+
+    // a slow handler and callers on several threads, each reply handed to its caller straight away
+    // fetch replies are told apart by channel only, each server and client gets one to itself
+    let slow_channel = session.write().unwrap().create_channel("MQ_SLOW_SERVER".to_string(), None).unwrap();
+    let slow_requests = stub_queue(&slow_channel, "rpc.slow")?;
+    let slow_server = RpcServer::serve(slow_requests, |request| {
+        thread::sleep(Duration::from_millis(20));
+        Ok(request.body().to_vec())
+    });
+    let slow_client_channel = session.write().unwrap().create_channel("MQ_SLOW_CLIENT".to_string(), None).unwrap();
+    let slow_client = RpcClient::new(slow_client_channel, stub_chain("rpc.slow"))?;
+    let started = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for i in 0..10 {
+                    let reply = slow_client.call(format!("slow {i}").into_bytes(), Duration::from_secs(5)).unwrap();
+                    assert_eq!(reply.body_string().unwrap(), format!("slow {i}"));
+                }
+            });
+        }
+    });
+    // 40 calls of 20ms are served one after the other
+    assert!(started.elapsed() < Duration::from_secs(3), "slow calls took {:?}", started.elapsed());
+    drop(slow_server);
+    drop(slow_client);
+
+    // without a server the call times out, and its reply is skipped once one shows up
+    server.stop();
+    assert!(matches!(client.call(b"late".to_vec(), Duration::from_millis(100)), Err(MqError::Timeout)));
+    let server = serve(requests);
+    assert_eq!(client.call(b"again".to_vec(), timeout)?.body_string()?, "AGAIN");
+    assert_eq!(server.stats().delivered, 2);
+    assert_eq!(server.stats().handler_errors, 0);
+
+    drop(server);
+    drop(client);
+    session.write().unwrap().close();
+    println!("Rpc test passed!");
+    Ok(())
+}
@@ -4,19 +4,19 @@ use crate::mq::io::factory::{CommandType, DataType, MessageType, Routing, Routin
 use crate::mq::io::session;
 
 pub fn spmc_test() -> Result<(), Box<dyn std::error::Error>> {
-    let session = session::Session::new("127.0.0.1:11451", "MQ_HOST".to_string());
+    let session = session::Session::new("127.0.0.1:11451", "MQ_HOST".to_string())?;
     let session = Arc::from(RwLock::from(session));
     session
         .clone()
         .write()
         .unwrap()
-        .init(session.clone());
+        .init(session.clone())?;
 
-    session.write().unwrap().set_read_timeout(std::time::Duration::from_millis(300));
+    session.write().unwrap().set_read_timeout(std::time::Duration::from_millis(300))?;
 
-    let producer = session.write().unwrap().create_channel("MQ_CHANNEL_P".to_string()).unwrap();
-    let consumer1 = session.write().unwrap().create_channel("MQ_CHANNEL_C1".to_string()).unwrap();
-    let consumer2 = session.write().unwrap().create_channel("MQ_CHANNEL_C2".to_string()).unwrap();
+    let producer = session.write().unwrap().create_channel("MQ_CHANNEL_P".to_string(), None).unwrap();
+    let consumer1 = session.write().unwrap().create_channel("MQ_CHANNEL_C1".to_string(), None).unwrap();
+    let consumer2 = session.write().unwrap().create_channel("MQ_CHANNEL_C2".to_string(), None).unwrap();
 
     let msg = producer.read().unwrap().get_factory()
         .routing_mod(RoutingModFactory::new()
@@ -78,11 +78,11 @@ pub fn spmc_test() -> Result<(), Box<dyn std::error::Error>> {
                 .queue_name(String::from("base_queue"))
                 .build();
             if let Ok((head, data)) = consumer1.write().unwrap().send_and_read(msg) {
-                if head.unwrap().errcode == 0xfu16 {
+                if head.errcode == 0xfu16 {
                     println!("No msg.");
                     thread::sleep(std::time::Duration::from_millis(60));
                 } else {
-                    println!("Fetched from channel consumer 1: {:?}", String::from_utf8(*data).unwrap().trim_end_matches("\0"));
+                    println!("Fetched from channel consumer 1: {:?}", String::from_utf8(data.to_vec()).unwrap().trim_end_matches("\0"));
                 }
             } else {
                 println!("Failed to fetch from consumer 1!");
@@ -105,11 +105,11 @@ pub fn spmc_test() -> Result<(), Box<dyn std::error::Error>> {
                 .queue_name(String::from("base_queue"))
                 .build();
             if let Ok((head, data)) = consumer2.write().unwrap().send_and_read(msg) {
-                if head.unwrap().errcode == 0xfu16 {
+                if head.errcode == 0xfu16 {
                     println!("No msg.");
                     thread::sleep(std::time::Duration::from_millis(60));
                 } else {
-                    println!("Fetched from channel consumer 2: {:?}", String::from_utf8(*data).unwrap().trim_end_matches("\0"));
+                    println!("Fetched from channel consumer 2: {:?}", String::from_utf8(data.to_vec()).unwrap().trim_end_matches("\0"));
                 }
             } else {
                 println!("Failed to fetch from consumer 2!");
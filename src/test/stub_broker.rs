@@ -0,0 +1,296 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::mq::api::common::{ChannelApi, ChannelQueueApi};
+use crate::mq::api::message;
+use crate::mq::api::queue::Queue;
+use crate::mq::error::MqResult;
+use crate::mq::io::ack;
+use crate::mq::io::auth::{AuthError, Credentials};
+use crate::mq::io::channel::Channel;
+use crate::mq::io::config::SessionConfig;
+use crate::mq::io::factory::{AckKind, Command, MessageFactory, Routing};
+use crate::mq::io::session::Session;
+use crate::mq::io::transport::{MemoryListener, Transport};
+use crate::mq::protocol::proto::DataHead;
+use crate::mq::protocol::protobase::{Deserialize, Serialize};
+use crate::mq::routing::chain::{RoutingChain, RoutingChainFactory};
+
+// A tiny in-process broker speaking just enough of the protocol to drive the client in tests.
+#[derive(Clone)]
+pub struct StubBroker {
+    // keyed by "<virtual host>/<queue>"
+    pub queues: Arc<Mutex<HashMap<String, VecDeque<Vec<u8>>>>>,
+    // the interval offered in heartbeat replies, None leaves it to the client
+    pub heartbeat: Arc<Mutex<Option<Duration>>>,
+    // reads every frame and answers none, like a broker that hangs
+    pub silent: Arc<AtomicBool>,
+    // "<virtual host>/<name>" of every declaration received, replays included
+    pub declared: Arc<Mutex<Vec<String>>>,
+    // every channel a CLOSE-CH frame was received for, in order
+    pub closed: Arc<Mutex<Vec<String>>>,
+    // a handle on every connection accepted so far, see drop_connections
+    connections: Arc<Mutex<Vec<Box<dyn Transport>>>>,
+    // with credentials set, a connection has to log in before anything else or it is dropped
+    credentials: Option<Credentials>,
+    // every fetch the broker has served, long polls included
+    pub fetches: Arc<AtomicUsize>,
+    // pushes to these queues, keyed like `queues`, are dropped and nacked if the publisher asked for a confirm
+    pub refused: Arc<Mutex<HashSet<String>>>,
+    next_tag: Arc<AtomicU64>,
+}
+
+// a message fetched with manual acks, held until the consumer settles it
+struct Delivery {
+    channel: String,
+    queue: String,
+    item: Vec<u8>,
+}
+
+impl StubBroker {
+    pub fn spawn(listener: MemoryListener) -> StubBroker {
+        StubBroker::spawn_with_auth(listener, None)
+    }
+
+    pub fn spawn_with_auth(listener: MemoryListener, credentials: Option<Credentials>) -> StubBroker {
+        let broker = StubBroker {
+            queues: Arc::new(Mutex::new(HashMap::new())),
+            heartbeat: Arc::new(Mutex::new(None)),
+            silent: Arc::new(AtomicBool::new(false)),
+            declared: Arc::new(Mutex::new(vec![])),
+            closed: Arc::new(Mutex::new(vec![])),
+            connections: Arc::new(Mutex::new(vec![])),
+            credentials,
+            fetches: Arc::new(AtomicUsize::new(0)),
+            refused: Arc::new(Mutex::new(HashSet::new())),
+            next_tag: Arc::new(AtomicU64::new(1)),
+        };
+        let shared = broker.clone();
+        thread::spawn(move || {
+            while let Ok(conn) = listener.accept() {
+                if let Ok(handle) = conn.try_clone() {
+                    shared.connections.lock().unwrap().push(handle);
+                }
+                let broker = shared.clone();
+                thread::spawn(move || broker.serve(Box::new(conn)));
+            }
+        });
+        broker
+    }
+
+    // cuts every connection as if the network went away, the client sees it on its next read or write
+    pub fn drop_connections(&self) {
+        for conn in self.connections.lock().unwrap().drain(..) {
+            let _ = conn.shutdown();
+        }
+    }
+
+    // how many messages wait in `host_queue`, keyed like `queues`
+    pub fn queued(&self, host_queue: &str) -> usize {
+        self.queues.lock().unwrap().get(host_queue).map_or(0, |queue| queue.len())
+    }
+
+    fn serve(&self, conn: Box<dyn Transport>) {
+        let mut unacked = HashMap::new();
+        self.serve_frames(conn, &mut unacked);
+        // whatever the connection did not settle is delivered again
+        self.requeue(unacked.into_iter().collect());
+    }
+
+    fn requeue(&self, mut deliveries: Vec<(u64, Delivery)>) {
+        deliveries.sort_by_key(|(tag, _)| std::cmp::Reverse(*tag));
+        let mut queues = self.queues.lock().unwrap();
+        for (_, delivery) in deliveries {
+            queues.entry(delivery.queue).or_default().push_front(delivery.item);
+        }
+    }
+
+    fn serve_frames(&self, mut conn: Box<dyn Transport>, unacked: &mut HashMap<u64, Delivery>) {
+        let mut authenticated = self.credentials.is_none();
+        loop {
+            let mut buf_head = [0u8; 256];
+            if conn.read_exact(&mut buf_head).is_err() {
+                return;
+            }
+            let head = DataHead::deserialize(buf_head);
+            let mut data = vec![0u8; (head.slice_count * head.slice_size) as usize];
+            if conn.read_exact(&mut data).is_err() {
+                return;
+            }
+
+            if self.silent.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            let channel = trim(&head.channel);
+            let host = trim(&head.virtual_host);
+            let reply = match Command::parse(&head.command) {
+                Some(Command::Auth) => {
+                    let errcode = match (&self.credentials, Credentials::decode(&data)) {
+                        (None, _) => 0,
+                        (Some(expected), Some(given)) if *expected == given => 0,
+                        (Some(Credentials::Token(_)), Some(Credentials::Token(_))) => AuthError::TokenExpired.errcode(),
+                        _ => AuthError::InvalidCredentials.errcode(),
+                    };
+                    authenticated = errcode == 0;
+                    Some(reply(host, channel, Some(Command::Auth), vec![], errcode))
+                }
+                _ if !authenticated => return,
+                Some(Command::Heartbeat) => {
+                    let offered = self.heartbeat.lock().unwrap().map_or(0, |interval| interval.as_millis() as u32);
+                    Some(reply(host, channel, Some(Command::Heartbeat), offered.to_le_bytes().to_vec(), 0))
+                }
+                Some(Command::CloseChannel) => {
+                    self.closed.lock().unwrap().push(channel.clone());
+                    let tags = unacked.iter().filter(|(_, d)| d.channel == channel).map(|(tag, _)| *tag).collect::<Vec<_>>();
+                    self.requeue(tags.into_iter().filter_map(|tag| unacked.remove_entry(&tag)).collect());
+                    Some(reply(host, channel, Some(Command::CloseChannel), vec![], 0))
+                }
+                None if head.routing_mod[0] == 0 && head.routing_mod[1] == 0 => {
+                    // keep the message as it was pushed, without the padding
+                    data.truncate(head.body_len().unwrap_or(data.len()));
+                    let items = if head.count == 0 { vec![data] } else { unpack(&data) };
+                    let key = format!("{host}/{}", trim(&head.route3));
+                    let refused = self.refused.lock().unwrap().contains(&key);
+                    if !refused {
+                        self.queues.lock().unwrap().entry(key).or_default().extend(items);
+                    }
+                    // a publisher in confirm mode hears back under the sequence number it sent
+                    (head.ack == AckKind::Ack as u16).then(|| {
+                        let kind = if refused { AckKind::Reject } else { AckKind::Ack };
+                        ack::ack_frame(&host, &channel, None, head.delivery_tag(), kind).into_vec()
+                    })
+                }
+                None if head.routing_mod[0] == 0 && head.routing_mod[1] == 1 => {
+                    self.fetches.fetch_add(1, Ordering::SeqCst);
+                    // a long poll holds up the whole connection, which is good enough for tests
+                    let deadline = Instant::now() + Duration::from_millis(head.wait_ms() as u64);
+                    let key = format!("{host}/{}", trim(&head.route3));
+                    let items = loop {
+                        let mut queues = self.queues.lock().unwrap();
+                        let queue = queues.entry(key.clone()).or_default();
+                        let items = queue.drain(..queue.len().min(head.count.max(1) as usize)).collect::<Vec<_>>();
+                        if !items.is_empty() || Instant::now() >= deadline {
+                            break items;
+                        }
+                        drop(queues);
+                        thread::sleep(Duration::from_millis(5));
+                    };
+                    let mut tag = 0;
+                    if head.ack == AckKind::Ack as u16 && !items.is_empty() {
+                        tag = self.next_tag.fetch_add(items.len() as u64, Ordering::SeqCst);
+                        for (i, item) in items.iter().enumerate() {
+                            unacked.insert(tag + i as u64, Delivery { channel: channel.clone(), queue: key.clone(), item: item.clone() });
+                        }
+                    }
+                    let frame = match (items.len(), head.count) {
+                        (0, _) => reply(host, channel, None, vec![], 0xf),
+                        // a fetch without a count gets the message itself, as it always did
+                        (_, 0) => reply(host, channel, None, items.into_iter().next().unwrap(), 0),
+                        (count, _) => {
+                            let mut packed = vec![];
+                            items.iter().for_each(|item| message::pack(&mut packed, item));
+                            let frame = reply(host, channel, None, packed, 0);
+                            let mut packed_head = DataHead::deserialize(<[u8; 256]>::try_from(&frame[0..256]).unwrap());
+                            packed_head.count = count as u32;
+                            [packed_head.serialize().to_vec(), frame[256..].to_vec()].concat()
+                        }
+                    };
+                    Some(answering(frame, &head, tag))
+                }
+                None if head.routing_mod[0] == 0 && head.routing_mod[1] == 2 => {
+                    let requeue = AckKind::parse(head.ack) == Some(AckKind::Requeue);
+                    if let Some(delivery) = unacked.remove_entry(&head.delivery_tag()).filter(|_| requeue) {
+                        self.requeue(vec![delivery]);
+                    }
+                    None
+                }
+                None if head.routing_mod[0] == 1 => {
+                    self.declared.lock().unwrap().push(format!("{host}/{}", trim(&data)));
+                    None
+                }
+                None => None,
+            };
+            if let Some(reply) = reply {
+                if conn.write_all(&reply).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// A session with `config` on a stub broker of its own.
+pub fn stub_session(config: SessionConfig) -> MqResult<(StubBroker, Arc<RwLock<Session>>)> {
+    let (listener, connector) = MemoryListener::bind();
+    let broker = StubBroker::spawn(listener);
+    let session = Session::open_with(Box::new(connector), config)?;
+    Ok((broker, session))
+}
+
+// routes straight to the queue `name`, the way every stub test addresses its queues
+pub fn stub_chain(name: &str) -> RoutingChain {
+    RoutingChainFactory::new()
+        .add_key(Routing::Stop)
+        .set_queue_name(name.to_string())
+        .build()
+}
+
+// declares `name` on `channel` and opens it there
+pub fn stub_queue(channel: &Arc<RwLock<Channel>>, name: &str) -> MqResult<Queue> {
+    channel.write().unwrap().create_queue(name.to_string(), stub_chain(name))?;
+    channel.write().unwrap().get_queue(stub_chain(name))
+}
+
+// polls `condition` for up to two seconds
+pub fn wait_for<F: Fn() -> bool>(condition: F) -> Result<(), Box<dyn std::error::Error>> {
+    for _ in 0..200 {
+        if condition() {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Err("condition not reached in time".into())
+}
+
+pub fn reply(host: String, channel: String, command: Option<Command>, data: Vec<u8>, errcode: u16) -> Vec<u8> {
+    let mut factory = MessageFactory::new(host, channel).data(data);
+    if let Some(command) = command {
+        factory = factory.command(command);
+    }
+    let mut frame = factory.build();
+    let mut head = DataHead::deserialize(<[u8; 256]>::try_from(&frame[0..256]).unwrap());
+    head.errcode = errcode;
+    frame.splice(0..256, head.serialize());
+    frame
+}
+
+// fetch replies carry the routing of the request they answer and the tag of their first delivery
+fn answering(mut frame: Vec<u8>, request: &DataHead, delivery_tag: u64) -> Vec<u8> {
+    let mut head = DataHead::deserialize(<[u8; 256]>::try_from(&frame[0..256]).unwrap());
+    head.set_delivery_tag(delivery_tag);
+    head.routing_mod = request.routing_mod;
+    head.route0 = request.route0;
+    head.route1 = request.route1;
+    head.route2 = request.route2;
+    head.route3 = request.route3;
+    frame.splice(0..256, head.serialize());
+    frame
+}
+
+fn unpack(mut data: &[u8]) -> Vec<Vec<u8>> {
+    let mut items = vec![];
+    while data.len() >= 4 {
+        let len = u32::from_le_bytes(<[u8; 4]>::try_from(&data[0..4]).unwrap()) as usize;
+        items.push(data[4..4 + len].to_vec());
+        data = &data[4 + len..];
+    }
+    items
+}
+
+fn trim(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string()
+}
@@ -0,0 +1,68 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use crate::mq::api::subscription::{ErrorAction, ErrorPolicy, SubscriptionConfig, SubscriptionError, SubscriptionState};
+use crate::test::stub_broker::{stub_queue, stub_session};
+
+#[test]
+pub fn subscription_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (_broker, session) = stub_session(Default::default())?;
+
+    let channel = session.write().unwrap().create_channel("MQ_CHANNEL".to_string(), None).unwrap();
+    let queue = stub_queue(&channel, "jobs")?;
+    for i in 0..3 {
+        queue.push_string(format!("job {i}"))?;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let config = SubscriptionConfig::new().max_backoff(Duration::from_millis(40));
+    let mut subscription = queue.subscribe_with(config, move |message| {
+        tx.send(message.body_string()?)?;
+        Ok(())
+    });
+    for i in 0..3 {
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1))?, format!("job {i}"));
+    }
+
+    // nothing is delivered while paused
+    subscription.pause();
+    thread::sleep(Duration::from_millis(60));
+    queue.push_string(String::from("later"))?;
+    assert!(rx.recv_timeout(Duration::from_millis(120)).is_err());
+    subscription.resume();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1))?, "later");
+    // an idle queue is polled with growing pauses in between
+    thread::sleep(Duration::from_millis(100));
+    let empty = subscription.stats().empty_fetches;
+    assert!(empty > 0 && empty < 10);
+
+    subscription.cancel();
+    assert_eq!(subscription.state(), SubscriptionState::Cancelled);
+    assert_eq!(subscription.stats().delivered, 4);
+
+    // the error policy decides what a failing handler does to the subscription
+    let (errors_tx, errors_rx) = mpsc::channel();
+    let policy = ErrorPolicy::callback(move |err| {
+        let _ = errors_tx.send(matches!(err, SubscriptionError::Handler(_)));
+        ErrorAction::Cancel
+    });
+    let failing = queue.subscribe_with(SubscriptionConfig::new().error_policy(policy), |message| {
+        Err(format!("cannot handle {}", message.body_string()?).into())
+    });
+    queue.push_string(String::from("poison"))?;
+    assert!(errors_rx.recv_timeout(Duration::from_secs(1))?);
+    for _ in 0..50 {
+        if failing.state() == SubscriptionState::Cancelled {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(failing.state(), SubscriptionState::Cancelled);
+    assert_eq!(failing.stats().handler_errors, 1);
+
+    drop(failing);
+    drop(queue);
+    assert!(session.write().unwrap().close().is_clean());
+    println!("Subscription test passed!");
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::mq::io::transport::{TcpTransport, Transport};
+
+#[test]
+pub fn transport_test() -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let mut reader = TcpTransport::new(TcpStream::connect(listener.local_addr()?)?);
+    let (peer, _) = listener.accept()?;
+    let mut writer = reader.try_clone()?;
+
+    // polling the reading end while the writing clone is held up by a full socket buffer
+    // must not make the write fail half way
+    let body = vec![7u8; 8 << 20];
+    let done = AtomicBool::new(false);
+    let (mut peer, received) = thread::scope(|scope| {
+        let poller = scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                assert!(!reader.poll_ready(256).unwrap());
+            }
+        });
+        let receiver = scope.spawn(move || {
+            let mut peer = peer;
+            peer.set_read_timeout(Some(Duration::from_secs(2)))?;
+            let mut received = vec![0u8; 8 << 20];
+            thread::sleep(Duration::from_millis(100));
+            peer.read_exact(&mut received).map(|_| (peer, received))
+        });
+        // in small writes, each of them starts while the poller may be busy
+        let written = body.chunks(4096).try_for_each(|chunk| writer.write_all(chunk));
+        done.store(true, Ordering::SeqCst);
+        poller.join().unwrap();
+        written.and_then(|_| receiver.join().unwrap())
+    })?;
+    assert!(received == body);
+
+    // what has arrived is reported, and is still there to be read
+    peer.write_all(&[1u8; 256])?;
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !reader.poll_ready(256)? {
+        assert!(Instant::now() < deadline, "poll_ready never saw the bytes");
+    }
+    reader.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut buf = [0u8; 256];
+    reader.read_exact(&mut buf)?;
+    assert_eq!(buf, [1u8; 256]);
+    println!("Transport test passed!");
+    Ok(())
+}
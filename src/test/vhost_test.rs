@@ -0,0 +1,46 @@
+use crate::mq::api::common::{ChannelApi, ChannelQueueApi, FetchResultString};
+use crate::mq::io::factory::{CommandType, Routing};
+use crate::mq::routing::chain::RoutingChainFactory;
+use crate::test::stub_broker::stub_session;
+
+#[test]
+pub fn vhost_test() -> Result<(), Box<dyn std::error::Error>> {
+    let (broker, session) = stub_session(Default::default())?;
+
+    let chain = RoutingChainFactory::new()
+        .add_key(Routing::Route(String::from("orders_exc")))
+        .add_key(Routing::Stop)
+        .set_queue_name(String::from("orders"))
+        .build();
+    let mut queues = vec![];
+    for tenant in ["TENANT_A", "TENANT_B"] {
+        session.read().unwrap().create_virtual_host(tenant.to_string())?;
+        let channel = session.write().unwrap().create_channel(format!("{tenant}_CH"), Some(tenant.to_string())).unwrap();
+        assert_eq!(channel.read().unwrap().host_name(), tenant);
+        channel.write().unwrap().create_queue(String::from("orders"), chain.clone())?;
+        queues.push(channel.write().unwrap().get_queue(chain.clone())?);
+    }
+    // a channel name is taken no matter which virtual host asks for it
+    assert!(session.write().unwrap().create_channel("TENANT_A_CH".to_string(), None).is_none());
+
+    // the same queue name on two virtual hosts are two different queues
+    queues[0].push_string(String::from("for tenant a"))?;
+    assert!(matches!(queues[1].fetch_simple_string(), FetchResultString::FailedNoItem));
+    match queues[0].fetch_simple_string() {
+        FetchResultString::Success(s) => assert_eq!(s, "for tenant a"),
+        _ => return Err("expected a message on tenant a".into()),
+    }
+    assert!(broker.queues.lock().unwrap().contains_key("TENANT_B/orders"));
+
+    let topology = session.read().unwrap().topology();
+    assert_eq!(topology[0].command_type, CommandType::NewVirtualHost);
+    assert_eq!(topology.len(), 4);
+    session.read().unwrap().drop_virtual_host("TENANT_B".to_string())?;
+    let topology = session.read().unwrap().topology();
+    assert!(topology.iter().all(|d| d.host == "TENANT_A"));
+    assert_eq!(topology.len(), 2);
+
+    assert!(session.write().unwrap().close().is_clean());
+    println!("Virtual host test passed!");
+    Ok(())
+}
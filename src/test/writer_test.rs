@@ -0,0 +1,145 @@
+use std::io::{ErrorKind, IoSlice, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::mq::io::frame::Frame;
+use crate::mq::io::transport::Transport;
+use crate::mq::io::writer::{Writer, WriterConfig};
+
+// What the writer did to the connection: the bytes and how many went out per write call.
+#[derive(Clone, Default)]
+struct Wire {
+    bytes: Arc<Mutex<Vec<u8>>>,
+    writes: Arc<Mutex<Vec<usize>>>,
+    // bytes taken per write call, 0 for all of them
+    per_write: usize,
+    // bytes taken in total before every write fails with `error`
+    budget: Option<usize>,
+    error: Option<ErrorKind>,
+    shut_down: Arc<AtomicBool>,
+}
+
+impl Read for Wire {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(ErrorKind::WouldBlock.into())
+    }
+}
+
+impl Write for Wire {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let mut bytes = self.bytes.lock().unwrap();
+        let room = self.budget.map_or(usize::MAX, |budget| budget.saturating_sub(bytes.len()));
+        if room == 0 {
+            return Err(self.error.unwrap_or(ErrorKind::BrokenPipe).into());
+        }
+        let limit = room.min(if self.per_write == 0 { usize::MAX } else { self.per_write });
+        let taken = bufs.iter().flat_map(|buf| buf.iter()).take(limit).copied().collect::<Vec<_>>();
+        bytes.extend(&taken);
+        self.writes.lock().unwrap().push(taken.len());
+        Ok(taken.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Wire {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<(), std::io::Error> {
+        self.shut_down.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn poll_ready(&mut self, _len: usize) -> Result<bool, std::io::Error> {
+        Ok(false)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+fn frame(byte: u8, len: usize) -> Frame {
+    Frame::new(vec![byte; 16], vec![byte; len - 16 - 4], 4)
+}
+
+#[test]
+pub fn writer_test() -> Result<(), Box<dyn std::error::Error>> {
+    // frames queued together go out in one vectored write, up to the frame limit
+    let wire = Wire::default();
+    let writer = Writer::spawn(Box::new(wire.clone()), WriterConfig::new().max_batch_frames(3).linger(Duration::from_millis(50)));
+    (0..7).try_for_each(|i| writer.write_nowait(frame(i, 100)))?;
+    writer.flush()?;
+    assert_eq!(*wire.writes.lock().unwrap(), vec![300, 300, 100]);
+
+    // and up to the byte limit
+    let wire = Wire::default();
+    let writer = Writer::spawn(Box::new(wire.clone()), WriterConfig::new().max_batch_bytes(250).linger(Duration::from_millis(50)));
+    (0..5).try_for_each(|i| writer.write_nowait(frame(i, 100)))?;
+    writer.flush()?;
+    assert_eq!(*wire.writes.lock().unwrap(), vec![300, 200]);
+
+    // without a linger a frame that is alone in the queue goes out alone
+    let wire = Wire::default();
+    let writer = Writer::spawn(Box::new(wire.clone()), WriterConfig::new());
+    writer.write(frame(1, 100)).map_err(|(err, _)| err)?;
+    writer.write(frame(2, 100)).map_err(|(err, _)| err)?;
+    assert_eq!(*wire.writes.lock().unwrap(), vec![100, 100]);
+
+    // a blocking write does not wait out the linger
+    let wire = Wire::default();
+    let writer = Writer::spawn(Box::new(wire.clone()), WriterConfig::new().linger(Duration::from_millis(500)));
+    let started = Instant::now();
+    writer.write(frame(1, 100)).map_err(|(err, _)| err)?;
+    assert!(started.elapsed() < Duration::from_millis(250), "write took {:?}", started.elapsed());
+    assert_eq!(*wire.writes.lock().unwrap(), vec![100]);
+
+    // a connection that takes a few bytes per call still gets every frame whole and in order
+    let wire = Wire { per_write: 7, ..Wire::default() };
+    let writer = Writer::spawn(Box::new(wire.clone()), WriterConfig::new().linger(Duration::from_millis(20)));
+    (0..4).try_for_each(|i| writer.write_nowait(frame(i, 40 + i as usize)))?;
+    writer.flush()?;
+    let expected = (0..4).flat_map(|i| frame(i, 40 + i as usize).into_vec()).collect::<Vec<_>>();
+    assert_eq!(*wire.bytes.lock().unwrap(), expected);
+
+    // a failure on a frame boundary fails the frames that did not go out, and hands them back
+    let wire = Wire { budget: Some(200), error: Some(ErrorKind::TimedOut), ..Wire::default() };
+    let writer = Writer::spawn(Box::new(wire.clone()), WriterConfig::new().linger(Duration::from_millis(50)));
+    let results = std::thread::scope(|scope| {
+        let writes = (0..3).map(|i| {
+            let writer = &writer;
+            let result = scope.spawn(move || writer.write(frame(i, 100)));
+            std::thread::sleep(Duration::from_millis(5));
+            result
+        }).collect::<Vec<_>>();
+        writes.into_iter().map(|write| write.join().unwrap()).collect::<Vec<_>>()
+    });
+    assert!(results[0].is_ok() && results[1].is_ok());
+    let (err, returned) = results[2].as_ref().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert_eq!(returned.clone().into_vec(), frame(2, 100).into_vec());
+    assert!(!wire.shut_down.load(Ordering::SeqCst));
+
+    // a timeout part way through a frame leaves the connection out of step, it is given up
+    let wire = Wire { budget: Some(150), error: Some(ErrorKind::TimedOut), ..Wire::default() };
+    let writer = Writer::spawn(Box::new(wire.clone()), WriterConfig::new());
+    writer.write(frame(1, 100)).map_err(|(err, _)| err)?;
+    let (err, _) = writer.write(frame(2, 100)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+    assert!(wire.shut_down.load(Ordering::SeqCst));
+
+    println!("Writer test passed!");
+    Ok(())
+}